@@ -0,0 +1,126 @@
+use moon::*;
+use reqwest::header;
+use std::env;
+use std::error::Error;
+use std::fs;
+
+const DEFAULT_BASE_URL: &str = "https://api.assemblyai.com/v2";
+
+// ------ ------
+//     Types
+// ------ ------
+
+// Types for AAI data structures (used in deserialize calls), we only keep the bits we use
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct UploadResp {
+    upload_url: String, // url of file we uploaded (only accessible from AAI servers)
+}
+
+// Just enough of the transcript to know where the job stands, the whole thing is left to
+// `shared::transcription::AssemblyAi` once it's completed
+#[derive(Serialize, Debug)]
+#[serde(crate = "serde")]
+struct TranscriptRequest<'a> {
+    audio_url: &'a str,
+    speaker_labels: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+pub struct Transcript {
+    // https://docs.assemblyai.com/core-transcription
+    pub id: String,
+    pub status: String, // queued, processing, completed, error
+    pub error: Option<String>,
+}
+
+// The key comes from JADILI_AAI_KEY or, like the research scripts, from `auth_aai.txt` (not in VCS)
+pub fn auth_key() -> Option<String> {
+    env::var("JADILI_AAI_KEY")
+        .ok()
+        .or_else(|| fs::read_to_string("auth_aai.txt").ok())
+}
+
+// ------ ------
+//    Client
+// ------ ------
+
+pub struct AssemblyAi {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl AssemblyAi {
+    pub fn new(base_url: impl Into<String>, auth_key: &str) -> Result<Self, Box<dyn Error>> {
+        // Build a client with persistent headers
+        let mut headers = header::HeaderMap::new();
+        let mut auth_value = header::HeaderValue::from_str(auth_key.trim())?;
+        auth_value.set_sensitive(true);
+        headers.insert(header::AUTHORIZATION, auth_value);
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+        Ok(Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        })
+    }
+
+    // JADILI_AAI_BASE_URL points us at a mock server
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let base_url = env::var("JADILI_AAI_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.into());
+        let auth_key = auth_key().ok_or("no JADILI_AAI_KEY or auth_aai.txt")?;
+        Self::new(base_url, &auth_key)
+    }
+
+    // POST our file to the server, and get the location from the response
+    pub async fn upload(&self, recording: Vec<u8>) -> reqwest::Result<String> {
+        let up_resp = self
+            .client
+            .post(format!("{}/upload", self.base_url))
+            .body(recording)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(up_resp.json::<UploadResp>().await?.upload_url)
+    }
+
+    // Submit uploaded file for transcription, and get transcript id for which we will poll
+    pub async fn submit(&self, upload_url: &str) -> reqwest::Result<String> {
+        let request = TranscriptRequest {
+            audio_url: upload_url,
+            speaker_labels: true,
+        };
+
+        let tx_resp = self
+            .client
+            .post(format!("{}/transcript", self.base_url))
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Transcript>()
+            .await?;
+        Ok(tx_resp.id)
+    }
+
+    // The transcript's status, plus the raw body for normalizing once it's completed
+    pub async fn poll(&self, transcript_id: &str) -> Result<(Transcript, String), Box<dyn Error>> {
+        let raw = self
+            .client
+            .get(format!("{}/transcript/{}", self.base_url, transcript_id))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let transcript = serde_json::from_str(&raw)?;
+        Ok((transcript, raw))
+    }
+}
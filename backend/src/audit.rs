@@ -0,0 +1,103 @@
+use crate::catalog;
+use moon::*;
+use shared::audit::AuditEntry;
+use shared::{BlockId, EventId};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// ------ ------
+//    States
+// ------ ------
+
+// The next entry's `seq` per event, counted from the log the first time we write to it
+static NEXT_SEQ: Mutex<BTreeMap<EventId, u64>> = Mutex::new(BTreeMap::new());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+fn audit_path(event_id: EventId) -> PathBuf {
+    catalog::event_dir(event_id).join("audit.jsonl")
+}
+
+// Appends to the event's log, numbering the entries as they go in. The log is only ever added
+// to: it's the record of who did what, whatever later happened to the blocks.
+pub fn append(event_id: EventId, entries: Vec<AuditEntry>) {
+    if entries.is_empty() {
+        return;
+    }
+    let mut next_seq = NEXT_SEQ.lock().unwrap();
+    let seq = next_seq.entry(event_id).or_insert_with(|| {
+        read_entries(event_id)
+            .last()
+            .map_or(0, |entry| entry.seq + 1)
+    });
+    if let Err(err) = write_entries(event_id, entries, seq) {
+        eprintln!(
+            "Failed to write the audit log for event {}: {:?}",
+            event_id, err
+        );
+    }
+}
+
+// The log went with the deleted event, a new event given its id numbers its own from 0
+pub fn forget(event_id: EventId) {
+    NEXT_SEQ.lock().unwrap().remove(&event_id);
+}
+
+// Oldest first, every block's or only `block_id`'s
+pub fn entries(event_id: EventId, block_id: Option<BlockId>) -> Vec<AuditEntry> {
+    let mut entries = read_entries(event_id);
+    if let Some(block_id) = block_id {
+        entries.retain(|entry| entry.block_id == block_id);
+    }
+    entries
+}
+
+pub fn entry(event_id: EventId, seq: u64) -> Option<AuditEntry> {
+    read_entries(event_id)
+        .into_iter()
+        .find(|entry| entry.seq == seq)
+}
+
+fn write_entries(
+    event_id: EventId,
+    entries: Vec<AuditEntry>,
+    seq: &mut u64,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path(event_id))?;
+    for mut entry in entries {
+        entry.seq = *seq;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        *seq += 1;
+    }
+    Ok(())
+}
+
+// A line we can't parse (the tail of a write cut short by a crash) is skipped, not fatal
+fn read_entries(event_id: EventId) -> Vec<AuditEntry> {
+    let file = match File::open(audit_path(event_id)) {
+        Ok(file) => file,
+        Err(_) => return Vec::new(), // Nothing changed yet
+    };
+    BufReader::new(file)
+        .lines()
+        .filter_map(|line| {
+            let line = line.ok()?;
+            match serde_json::from_str(&line) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    eprintln!("Skipping audit entry of event {}: {}", event_id, err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,212 @@
+use moon::actix_web::http::header;
+use moon::actix_web::HttpRequest;
+use rand::RngCore;
+use shared::accounts::{self, load_accounts, save_accounts, verify_password, Account};
+use shared::roles::Role;
+use shared::{AccountSummary, EventId, LoginAccepted, RoleAssignment};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_ACCOUNTS_FILE: &str = "accounts.json";
+// A token nobody has used for this long has to log in again
+const TOKEN_IDLE_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+
+// ------ ------
+//     Types
+// ------ ------
+
+struct Login {
+    username: String,
+    last_used: Instant,
+}
+
+// ------ ------
+//    States
+// ------ ------
+
+// Keyed by token. Only in memory: after a restart everyone logs in again.
+static LOGINS: Mutex<BTreeMap<String, Login>> = Mutex::new(BTreeMap::new());
+// Held while changing the accounts file, so two admins don't overwrite each other's changes
+static ACCOUNTS_WRITE: Mutex<()> = Mutex::new(());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Accounts are made with `research add-user`, in JADILI_ACCOUNTS or `accounts.json` (not in VCS)
+fn accounts_path() -> PathBuf {
+    env::var("JADILI_ACCOUNTS")
+        .unwrap_or_else(|_| DEFAULT_ACCOUNTS_FILE.to_string())
+        .into()
+}
+
+fn read_accounts() -> Result<Vec<Account>, String> {
+    load_accounts(&accounts_path()).map_err(|err| {
+        eprintln!("Cannot read accounts: {:?}", err);
+        "The accounts can't be read right now".to_string()
+    })
+}
+
+// A new token if the password is right. Which of the two was wrong isn't given away.
+pub fn log_in(username: &str, password: &str) -> Result<LoginAccepted, String> {
+    let accounts = read_accounts()?;
+    let account = accounts
+        .iter()
+        .find(|account| account.username == username)
+        .filter(|account| verify_password(account, password))
+        .ok_or("Wrong username or password")?;
+
+    let token = new_token();
+    let login = Login {
+        username: account.username.clone(),
+        last_used: Instant::now(),
+    };
+    LOGINS.lock().unwrap().insert(token.clone(), login);
+    println!("{} logged in", account.username);
+    Ok(LoginAccepted {
+        username: account.username.clone(),
+        token,
+        role: account.role_for(None),
+    })
+}
+
+pub fn log_out(token: &str) {
+    if let Some(login) = LOGINS.lock().unwrap().remove(token) {
+        println!("{} logged out", login.username);
+    }
+}
+
+// Who sent a message carrying `token`, if anyone. The frontend's connection attaches its token
+// to every UpMsg, so this holds for the session that logged in, and for the new session a reload
+// of it opens.
+pub fn authenticate(token: Option<&str>) -> Option<String> {
+    authenticate_at(token, Instant::now())
+}
+
+fn authenticate_at(token: Option<&str>, now: Instant) -> Option<String> {
+    let mut logins = LOGINS.lock().unwrap();
+    let token = token?;
+    let login = logins.get_mut(token)?;
+    if now.duration_since(login.last_used) > TOKEN_IDLE_TIMEOUT {
+        println!("{}'s token expired", login.username);
+        logins.remove(token);
+        return None;
+    }
+    login.last_used = now;
+    Some(login.username.clone())
+}
+
+// The token of a request that doesn't come over the connection (an upload, an export), sent as
+// `Authorization: Bearer <token>`
+pub fn bearer_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// What `username` may do in `event_id`, or outside any event. Read from the accounts file every
+// time, so a role an admin changes holds from the user's next message on. An account that has
+// gone (or a file we can't read) can only look.
+pub fn role(username: &str, event_id: Option<EventId>) -> Role {
+    read_accounts()
+        .ok()
+        .and_then(|accounts| {
+            accounts
+                .iter()
+                .find(|account| account.username == username)
+                .map(|account| account.role_for(event_id))
+        })
+        .unwrap_or(Role::Viewer)
+}
+
+pub fn account_summaries() -> Result<Vec<AccountSummary>, String> {
+    Ok(read_accounts()?.iter().map(summary).collect())
+}
+
+// Returns every account as it stands after the change
+pub fn set_role(assignment: &RoleAssignment) -> Result<Vec<AccountSummary>, String> {
+    let _write = ACCOUNTS_WRITE.lock().unwrap();
+    let mut accounts = read_accounts()?;
+    if !accounts::set_role(
+        &mut accounts,
+        &assignment.username,
+        assignment.event_id,
+        assignment.role,
+    ) {
+        return Err(format!("There is no user {}", assignment.username));
+    }
+    save_accounts(&accounts_path(), &accounts).map_err(|err| {
+        eprintln!("Cannot write accounts: {:?}", err);
+        "The accounts can't be changed right now".to_string()
+    })?;
+    Ok(accounts.iter().map(summary).collect())
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn summary(account: &Account) -> AccountSummary {
+    AccountSummary {
+        username: account.username.clone(),
+        role: account.role,
+        event_roles: account.event_roles.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logged_in(token: &str, at: Instant) {
+        let login = Login {
+            username: "ann".to_string(),
+            last_used: at,
+        };
+        LOGINS.lock().unwrap().insert(token.to_string(), login);
+    }
+
+    #[test]
+    fn a_token_in_use_stays_good() {
+        let start = Instant::now();
+        logged_in("in-use", start);
+        let hour = Duration::from_secs(60 * 60);
+        assert_eq!(
+            authenticate_at(Some("in-use"), start + hour).as_deref(),
+            Some("ann")
+        );
+        // Idle for longer than the timeout since the login, but not since its last use
+        let later = start + hour + TOKEN_IDLE_TIMEOUT;
+        assert_eq!(
+            authenticate_at(Some("in-use"), later).as_deref(),
+            Some("ann")
+        );
+    }
+
+    #[test]
+    fn an_idle_token_expires_for_good() {
+        let start = Instant::now();
+        logged_in("idle", start);
+        let expired = start + TOKEN_IDLE_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(authenticate_at(Some("idle"), expired), None);
+        assert!(!LOGINS.lock().unwrap().contains_key("idle"));
+        assert_eq!(authenticate_at(Some("idle"), start), None);
+    }
+
+    #[test]
+    fn no_or_unknown_token_is_nobody() {
+        assert_eq!(authenticate(None), None);
+        assert_eq!(authenticate(Some("never-issued")), None);
+    }
+}
@@ -0,0 +1,311 @@
+use moon::*;
+use shared::speakers::{SpeakerProfile, SpeakerProfiles};
+use shared::{EventId, EventStatus, EventSummary};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const AUDIO_FILE: &str = "__event_audio.wav";
+
+// ------ ------
+//     Types
+// ------ ------
+
+// The on-disk `event.json`; every field has a default so hand-written manifests can stay short
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde", default)]
+pub struct EventManifest {
+    pub id: EventId,
+    pub title: String,
+    pub date: String,
+    pub language: String,
+    pub audio_file: String,
+    pub status: EventStatus,
+    pub speakers: SpeakerProfiles, // diarization label (A, B, ...) -> who they are
+    pub realtime_url: Option<String>, // a live event's transcript stream (wss://...)
+}
+
+impl Default for EventManifest {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            title: String::new(),
+            date: String::new(),
+            language: "en_us".to_string(),
+            audio_file: AUDIO_FILE.to_string(),
+            status: EventStatus::Pending,
+            speakers: SpeakerProfiles::new(),
+            realtime_url: None,
+        }
+    }
+}
+
+impl EventManifest {
+    // What we assume about an event directory somebody populated by hand
+    fn for_event(id: EventId) -> Self {
+        let status = if event_dir(id).join("__event_finished").exists() {
+            EventStatus::Finished
+        } else {
+            EventStatus::Pending
+        };
+        Self {
+            id,
+            title: format!("Event {}", id),
+            status,
+            ..Self::default()
+        }
+    }
+
+    // Uploads keep their own extension, so only the manifest knows the file's name
+    pub fn audio_url(&self) -> String {
+        format!(
+            "/_api/public/assets/{}/{}",
+            event_dir_name(self.id),
+            self.audio_file
+        )
+    }
+
+    pub fn to_summary(&self) -> EventSummary {
+        EventSummary {
+            id: self.id,
+            title: self.title.clone(),
+            date: self.date.clone(),
+            language: self.language.clone(),
+            audio_url: self.audio_url(),
+            status: self.status,
+            speakers: self.speakers.clone(),
+        }
+    }
+}
+
+// ------ ------
+//    States
+// ------ ------
+
+// Held while changing a manifest, so a status change and a rename racing it don't lose one another
+static MANIFEST_WRITE: Mutex<()> = Mutex::new(());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+fn event_dir_name(event_id: EventId) -> String {
+    format!("event_{:04}", event_id)
+}
+
+// Everything an event owns (audio, block files, stored document, manifest) lives in one directory
+#[cfg(not(test))]
+fn assets_dir() -> PathBuf {
+    PathBuf::from("./public/assets")
+}
+
+// Tests make events of their own, keep them out of the real catalog
+#[cfg(test)]
+fn assets_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("jadili-assets-{}", std::process::id()))
+}
+
+pub fn event_dir(event_id: EventId) -> PathBuf {
+    assets_dir().join(event_dir_name(event_id))
+}
+
+fn manifest_path(event_id: EventId) -> PathBuf {
+    event_dir(event_id).join("event.json")
+}
+
+// Every event directory under the assets, in id order; directories without a manifest still count
+pub fn list_events() -> Vec<EventManifest> {
+    let entries = match fs::read_dir(assets_dir()) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Cannot read the event catalog: {:?}", err);
+            return Vec::new();
+        }
+    };
+    let mut event_ids: Vec<EventId> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if !entry.file_type().ok()?.is_dir() {
+                return None;
+            }
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("event_")?
+                .parse()
+                .ok()
+        })
+        .collect();
+    event_ids.sort_unstable();
+    event_ids.into_iter().map(load_manifest).collect()
+}
+
+// For reading. A manifest we can't read is taken for a hand-made event's, see update_manifest.
+pub fn load_manifest(event_id: EventId) -> EventManifest {
+    match read_manifest(event_id) {
+        Ok(Some(manifest)) => manifest,
+        _ => EventManifest::for_event(event_id),
+    }
+}
+
+// Changes the manifest, one change at a time, and saves it if `f` changed anything. Refuses a
+// manifest it can't read rather than saving defaults over it.
+pub fn update_manifest<R>(
+    event_id: EventId,
+    f: impl FnOnce(&mut EventManifest) -> R,
+) -> Result<R, Box<dyn Error>> {
+    let _write = MANIFEST_WRITE.lock().unwrap();
+    if !event_exists(event_id) {
+        return Err(format!("There is no event {}", event_id).into());
+    }
+    let mut manifest = match read_manifest(event_id)? {
+        Some(manifest) => manifest,
+        None => EventManifest::for_event(event_id),
+    };
+    let before = manifest.clone();
+    let result = f(&mut manifest);
+    if manifest != before {
+        save_manifest(&manifest)?;
+    }
+    Ok(result)
+}
+
+// Write to the side and rename, so nobody ever reads half a manifest
+fn save_manifest(manifest: &EventManifest) -> Result<(), Box<dyn Error>> {
+    write_manifest(&manifest_path(manifest.id), manifest)
+}
+
+// Written beside `path` and renamed over it, so a reader gets the old manifest or the new one and
+// never half of one
+fn write_manifest(path: &Path, manifest: &EventManifest) -> Result<(), Box<dyn Error>> {
+    let tmp_path = path.with_extension("json.tmp");
+    serde_json::to_writer_pretty(File::create(&tmp_path)?, manifest)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+// Allocates the next free event id by claiming its directory, then writes the starting manifest
+pub fn create_event(
+    title: String,
+    date: String,
+    language: Option<String>,
+    audio_extension: String,
+) -> Result<EventManifest, Box<dyn Error>> {
+    fs::create_dir_all(assets_dir())?;
+    let mut id = list_events().last().map_or(1, |manifest| manifest.id + 1);
+    // `create_dir` fails if somebody else claimed the id since we listed, just try the next one
+    while let Err(err) = fs::create_dir(event_dir(id)) {
+        if err.kind() != ErrorKind::AlreadyExists {
+            return Err(err.into());
+        }
+        id += 1;
+    }
+
+    let mut manifest = EventManifest {
+        id,
+        title,
+        date,
+        audio_file: format!("__event_audio.{}", audio_extension),
+        ..EventManifest::default()
+    };
+    if let Some(language) = language {
+        manifest.language = language;
+    }
+    save_manifest(&manifest)?;
+    Ok(manifest)
+}
+
+pub fn event_exists(event_id: EventId) -> bool {
+    event_dir(event_id).is_dir()
+}
+
+// Removes the event's directory and everything in it. Under the manifest lock, so nobody is
+// halfway through saving the manifest into it.
+pub fn delete_event(event_id: EventId) -> Result<(), Box<dyn Error>> {
+    let _write = MANIFEST_WRITE.lock().unwrap();
+    if !event_exists(event_id) {
+        return Err(format!("There is no event {}", event_id).into());
+    }
+    fs::remove_dir_all(event_dir(event_id))?;
+    Ok(())
+}
+
+pub fn set_status(event_id: EventId, status: EventStatus) {
+    if let Err(err) = update_manifest(event_id, |manifest| manifest.status = status) {
+        eprintln!("Failed to save manifest for event {}: {:?}", event_id, err);
+    }
+}
+
+// Gives the label the profile `change` makes of the one it has, and returns it
+pub fn update_speaker_profile(
+    event_id: EventId,
+    label: &str,
+    change: impl FnOnce(SpeakerProfile) -> SpeakerProfile,
+) -> Result<SpeakerProfile, Box<dyn Error>> {
+    update_manifest(event_id, |manifest| {
+        let profile = change(manifest.speakers.remove(label).unwrap_or_default());
+        manifest.speakers.insert(label.to_string(), profile.clone());
+        profile
+    })
+}
+
+// None if the event has no manifest (yet)
+fn read_manifest(event_id: EventId) -> Result<Option<EventManifest>, Box<dyn Error>> {
+    let file = match File::open(manifest_path(event_id)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let manifest: EventManifest = serde_json::from_reader(BufReader::new(file))?;
+    Ok(Some(EventManifest {
+        id: event_id, // The directory wins if the two ever disagree
+        ..manifest
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // A directory of the test's own under the system's temp dir, empty to start with
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("jadili-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn manifest(title: &str) -> EventManifest {
+        EventManifest {
+            id: 7,
+            title: title.to_string(),
+            ..EventManifest::default()
+        }
+    }
+
+    fn read(path: &Path) -> EventManifest {
+        serde_json::from_reader(File::open(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_saved_manifest_replaces_the_old_one_whole() {
+        let path = scratch_dir("manifest-saved").join("event.json");
+        write_manifest(&path, &manifest("Council")).unwrap();
+        write_manifest(&path, &manifest("Budget hearing")).unwrap();
+        assert_eq!(read(&path), manifest("Budget hearing"));
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn a_failed_save_leaves_the_old_manifest_alone() {
+        let path = scratch_dir("manifest-failed").join("event.json");
+        write_manifest(&path, &manifest("Council")).unwrap();
+        // Nothing can be written where the new manifest goes first
+        fs::create_dir(path.with_extension("json.tmp")).unwrap();
+        assert!(write_manifest(&path, &manifest("Never saved")).is_err());
+        assert_eq!(read(&path), manifest("Council"));
+    }
+}
@@ -0,0 +1,572 @@
+use crate::auth;
+use crate::catalog::{self, EventManifest};
+use crate::store::{self, StoredBlock};
+use moon::actix_web::http::header;
+use moon::actix_web::{web, HttpRequest, HttpResponse};
+use moon::*;
+use shared::roles::Role;
+use shared::speakers::display_name;
+use shared::EventId;
+use std::io::{self, Cursor, Write};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+// Captions are read, not studied: two short lines at most
+const MAX_LINES: usize = 2;
+
+// ------ ------
+//     Types
+// ------ ------
+
+// The caption limits only matter to SRT/WebVTT, `timestamps` only to the transcripts
+#[derive(Deserialize, Clone, Debug)]
+#[serde(crate = "serde", default)]
+pub struct ExportOptions {
+    pub max_line_length: usize,
+    pub max_caption_ms: usize,
+    pub timestamps: bool,
+}
+
+impl Default for ExportOptions {
+    // The usual broadcast guidelines, 42 characters a line and no more than ~6 seconds on screen
+    fn default() -> Self {
+        Self {
+            max_line_length: 42,
+            max_caption_ms: 6_000,
+            timestamps: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Srt,
+    WebVtt,
+    Text,
+    Markdown,
+    Docx,
+}
+
+impl ExportFormat {
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "srt" => Some(Self::Srt),
+            "vtt" => Some(Self::WebVtt),
+            "txt" => Some(Self::Text),
+            "md" => Some(Self::Markdown),
+            "docx" => Some(Self::Docx),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Srt => "srt",
+            Self::WebVtt => "vtt",
+            Self::Text => "txt",
+            Self::Markdown => "md",
+            Self::Docx => "docx",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Srt => "application/x-subrip; charset=utf-8",
+            Self::WebVtt => "text/vtt; charset=utf-8",
+            Self::Text => "text/plain; charset=utf-8",
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Docx => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Caption {
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+}
+
+// A word of the text we publish, and when it was said
+#[derive(Debug)]
+struct TimedWord {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+// One block of a transcript, as the minutes show it
+#[derive(Debug)]
+struct Paragraph {
+    speaker: String,
+    start: usize,
+    text: String,
+}
+
+// ------ ------
+//   Handlers
+// ------ ------
+
+// GET /export/{event_id}/{srt|vtt|txt|md|docx}, with `?max_line_length=42&max_caption_ms=6000`
+// for captions and `?timestamps=true` for transcripts. Takes a login that can read the event, its
+// token as `Authorization: Bearer <token>`.
+pub async fn export_event(
+    request: HttpRequest,
+    path: web::Path<(EventId, String)>,
+    query: web::Query<ExportOptions>,
+) -> HttpResponse {
+    let (event_id, extension) = path.into_inner();
+    let user = match auth::authenticate(auth::bearer_token(&request)) {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    if auth::role(&user, Some(event_id)) < Role::Viewer {
+        println!("Refusing an export of event {} to {}", event_id, user);
+        return HttpResponse::Forbidden().finish();
+    }
+    let format = match ExportFormat::from_extension(&extension) {
+        Some(format) => format,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let options = query.into_inner();
+    if options.max_line_length == 0 || options.max_caption_ms == 0 {
+        return HttpResponse::BadRequest().body("Caption limits must be positive");
+    }
+    println!("Export event {} as {:?} {:?}", event_id, format, options);
+
+    let manifest = catalog::load_manifest(event_id);
+    let body = match format {
+        ExportFormat::Srt | ExportFormat::WebVtt => {
+            let captions = store::with_document(event_id, |document| {
+                build_captions(document.blocks.iter(), &options)
+            });
+            render_captions(&captions, format).into_bytes()
+        }
+        ExportFormat::Text | ExportFormat::Markdown | ExportFormat::Docx => {
+            let paragraphs = store::with_document(event_id, |document| {
+                build_paragraphs(document.blocks.iter(), &manifest)
+            });
+            match format {
+                ExportFormat::Text => render_text(&manifest, &paragraphs, &options).into_bytes(),
+                ExportFormat::Markdown => {
+                    render_markdown(&manifest, &paragraphs, &options).into_bytes()
+                }
+                _ => match render_docx(&manifest, &paragraphs, &options) {
+                    Ok(docx) => docx,
+                    Err(err) => {
+                        eprintln!("Failed to build DOCX for event {}: {:?}", event_id, err);
+                        return HttpResponse::InternalServerError().finish();
+                    }
+                },
+            }
+        }
+    };
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"event_{:04}.{}\"",
+                event_id,
+                format.extension()
+            ),
+        ))
+        .body(body)
+}
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Captions never span blocks, a new block is a new speaker (or at least a new thought)
+fn build_captions<'a>(
+    blocks: impl Iterator<Item = &'a StoredBlock>,
+    options: &ExportOptions,
+) -> Vec<Caption> {
+    let mut captions = Vec::new();
+    for block in blocks.filter(|block| block.is_visible()) {
+        let mut current: Vec<TimedWord> = Vec::new();
+        for word in timed_words(block) {
+            if let Some(first) = current.first() {
+                let mut texts: Vec<&str> = current.iter().map(|w| w.text.as_str()).collect();
+                texts.push(&word.text);
+                if wrap_lines(&texts, options.max_line_length).len() > MAX_LINES
+                    || word.end.saturating_sub(first.start) > options.max_caption_ms
+                {
+                    captions.push(to_caption(std::mem::take(&mut current), options));
+                }
+            }
+            current.push(word);
+        }
+        if !current.is_empty() {
+            captions.push(to_caption(current, options));
+        }
+    }
+    captions
+}
+
+fn render_captions(captions: &[Caption], format: ExportFormat) -> String {
+    let mut out = String::new();
+    if let ExportFormat::WebVtt = format {
+        out.push_str("WEBVTT\n\n");
+    }
+    for (idx, caption) in captions.iter().enumerate() {
+        let separator = match format {
+            ExportFormat::WebVtt => '.',
+            _ => ',',
+        };
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            idx + 1,
+            timestamp(caption.start, separator),
+            timestamp(caption.end, separator),
+            caption.lines.join("\n")
+        ));
+    }
+    out
+}
+
+// One paragraph per block still showing in the editor, named the way the manifest names speakers
+fn build_paragraphs<'a>(
+    blocks: impl Iterator<Item = &'a StoredBlock>,
+    manifest: &EventManifest,
+) -> Vec<Paragraph> {
+    blocks
+        .filter(|block| block.is_visible())
+        .map(|block| Paragraph {
+            speaker: display_name(&manifest.speakers, &block.speaker),
+            start: block.words.first().map_or(0, |word| word.start),
+            text: block.text(),
+        })
+        .collect()
+}
+
+fn render_text(
+    manifest: &EventManifest,
+    paragraphs: &[Paragraph],
+    options: &ExportOptions,
+) -> String {
+    let mut out = format!("{}\n", manifest.title);
+    if !manifest.date.is_empty() {
+        out.push_str(&format!("{}\n", manifest.date));
+    }
+    for paragraph in paragraphs {
+        out.push('\n');
+        if options.timestamps {
+            out.push_str(&format!("[{}] ", clock(paragraph.start)));
+        }
+        out.push_str(&format!("{}: {}\n", paragraph.speaker, paragraph.text));
+    }
+    out
+}
+
+fn render_markdown(
+    manifest: &EventManifest,
+    paragraphs: &[Paragraph],
+    options: &ExportOptions,
+) -> String {
+    let mut out = format!("# {}\n", markdown_escape(&manifest.title));
+    if !manifest.date.is_empty() {
+        out.push_str(&format!("\n_{}_\n", markdown_escape(&manifest.date)));
+    }
+    for paragraph in paragraphs {
+        out.push_str(&format!("\n**{}**", markdown_escape(&paragraph.speaker)));
+        if options.timestamps {
+            out.push_str(&format!(" `{}`", clock(paragraph.start)));
+        }
+        out.push_str(&format!(": {}\n", markdown_escape(&paragraph.text)));
+    }
+    out
+}
+
+// The least a word processor will open: the content types, the package relationship, and the
+// document itself. Without a styles part the title is just big bold text.
+fn render_docx(
+    manifest: &EventManifest,
+    paragraphs: &[Paragraph],
+    options: &ExportOptions,
+) -> zip::result::ZipResult<Vec<u8>> {
+    let mut body = docx_paragraph(&[docx_run(&manifest.title, true, Some(36))]);
+    if !manifest.date.is_empty() {
+        body.push_str(&docx_paragraph(&[docx_run(&manifest.date, false, None)]));
+    }
+    for paragraph in paragraphs {
+        let mut runs = Vec::new();
+        if options.timestamps {
+            runs.push(docx_run(
+                &format!("[{}] ", clock(paragraph.start)),
+                false,
+                None,
+            ));
+        }
+        runs.push(docx_run(&format!("{}: ", paragraph.speaker), true, None));
+        runs.push(docx_run(&paragraph.text, false, None));
+        body.push_str(&docx_paragraph(&runs));
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let file_options = FileOptions::default();
+    zip.start_file("[Content_Types].xml", file_options)?;
+    zip.write_all(DOCX_CONTENT_TYPES.as_bytes())?;
+    zip.start_file("_rels/.rels", file_options)?;
+    zip.write_all(DOCX_RELS.as_bytes())?;
+    zip.start_file("word/document.xml", file_options)?;
+    write_docx_document(&mut zip, &body)?;
+    Ok(zip.finish()?.into_inner())
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// After an edit the words have been realigned to the corrected text, so they are the text we
+// publish either way
+fn timed_words(block: &StoredBlock) -> Vec<TimedWord> {
+    block
+        .words
+        .iter()
+        .map(|word| TimedWord {
+            start: word.start,
+            end: word.end,
+            text: word.text.clone(),
+        })
+        .collect()
+}
+
+fn to_caption(words: Vec<TimedWord>, options: &ExportOptions) -> Caption {
+    let texts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+    Caption {
+        start: words.first().map_or(0, |word| word.start),
+        end: words.last().map_or(0, |word| word.end),
+        lines: wrap_lines(&texts, options.max_line_length),
+    }
+}
+
+// Greedy wrap; a word longer than a line gets a line to itself rather than being broken up
+fn wrap_lines(words: &[&str], max_line_length: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in words {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= max_line_length => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+// 00:01:02,345 (SRT) or 00:01:02.345 (WebVTT)
+fn timestamp(ms: usize, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1_000 % 60,
+        separator,
+        ms % 1_000
+    )
+}
+
+// 01:02:03, block starts don't need the milliseconds
+fn clock(ms: usize) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1_000 % 60
+    )
+}
+
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// ------ DOCX ------
+
+const DOCX_CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/></Types>"#;
+
+const DOCX_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/></Relationships>"#;
+
+fn write_docx_document(out: &mut impl Write, body: &str) -> io::Result<()> {
+    write!(
+        out,
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#,
+        body
+    )
+}
+
+fn docx_paragraph(runs: &[String]) -> String {
+    format!("<w:p>{}</w:p>", runs.concat())
+}
+
+// `size` is in half-points, as Word counts them
+fn docx_run(text: &str, bold: bool, size: Option<usize>) -> String {
+    let mut properties = String::new();
+    if bold {
+        properties.push_str("<w:b/>");
+    }
+    if let Some(size) = size {
+        properties.push_str(&format!(r#"<w:sz w:val="{}"/>"#, size));
+    }
+    format!(
+        r#"<w:r><w:rPr>{}</w:rPr><w:t xml:space="preserve">{}</w:t></w:r>"#,
+        properties,
+        xml_escape(text)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: usize, words: &[(&str, usize, usize)], is_deleted: bool) -> StoredBlock {
+        let words: Vec<serde_json::Value> = words
+            .iter()
+            .map(|(text, start, end)| {
+                serde_json::json!({
+                    "confidence": 1.0,
+                    "start": start,
+                    "end": end,
+                    "speaker": "A",
+                    "text": text,
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "speaker": "A",
+            "words": words,
+            "corrected_text": null,
+            "is_deleted": is_deleted,
+            "merged_into": null,
+        }))
+        .unwrap()
+    }
+
+    // Two lines of 16 characters: the first caption fills up, the last two are more than
+    // six seconds apart, and the deleted block never shows
+    fn captions() -> Vec<Caption> {
+        let blocks = [
+            block(
+                1,
+                &[
+                    ("Welcome", 0, 500),
+                    ("everyone", 600, 1100),
+                    ("to", 1200, 1300),
+                    ("the", 1400, 1500),
+                    ("meeting.", 1600, 2200),
+                    ("Let's", 2400, 2700),
+                    ("begin", 2800, 3200),
+                ],
+                false,
+            ),
+            block(2, &[("Never", 3300, 3600), ("mind", 3700, 3900)], true),
+            block(
+                3,
+                &[("Thanks.", 4000, 4600), ("Right", 11000, 11400)],
+                false,
+            ),
+        ];
+        let options = ExportOptions {
+            max_line_length: 16,
+            ..ExportOptions::default()
+        };
+        build_captions(blocks.iter(), &options)
+    }
+
+    #[test]
+    fn srt_captions() {
+        assert_eq!(
+            render_captions(&captions(), ExportFormat::Srt),
+            "1\n\
+             00:00:00,000 --> 00:00:02,200\n\
+             Welcome everyone\n\
+             to the meeting.\n\
+             \n\
+             2\n\
+             00:00:02,400 --> 00:00:03,200\n\
+             Let's begin\n\
+             \n\
+             3\n\
+             00:00:04,000 --> 00:00:04,600\n\
+             Thanks.\n\
+             \n\
+             4\n\
+             00:00:11,000 --> 00:00:11,400\n\
+             Right\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn webvtt_captions() {
+        assert_eq!(
+            render_captions(&captions(), ExportFormat::WebVtt),
+            "WEBVTT\n\
+             \n\
+             1\n\
+             00:00:00.000 --> 00:00:02.200\n\
+             Welcome everyone\n\
+             to the meeting.\n\
+             \n\
+             2\n\
+             00:00:02.400 --> 00:00:03.200\n\
+             Let's begin\n\
+             \n\
+             3\n\
+             00:00:04.000 --> 00:00:04.600\n\
+             Thanks.\n\
+             \n\
+             4\n\
+             00:00:11.000 --> 00:00:11.400\n\
+             Right\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn lines_wrap_greedily() {
+        assert_eq!(
+            wrap_lines(&["a", "bb", "ccc", "d"], 6),
+            vec!["a bb", "ccc d"]
+        );
+        assert_eq!(
+            wrap_lines(&["a", "unbreakable", "b"], 6),
+            vec!["a", "unbreakable", "b"]
+        );
+        assert!(wrap_lines(&[], 6).is_empty());
+    }
+
+    #[test]
+    fn timestamps_and_clock() {
+        assert_eq!(timestamp(0, ','), "00:00:00,000");
+        assert_eq!(timestamp(3_723_045, ','), "01:02:03,045");
+        assert_eq!(timestamp(59_999, '.'), "00:00:59.999");
+        assert_eq!(clock(3_723_999), "01:02:03");
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(markdown_escape("*really* [sic]"), "\\*really\\* \\[sic\\]");
+        assert_eq!(xml_escape("<b> & \"c\""), "&lt;b&gt; &amp; &quot;c&quot;");
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{catalog, realtime, store, subscriptions};
+use moon::tokio::sync::mpsc::{self, UnboundedSender};
+use moon::tokio::time::{sleep, timeout, Duration};
+use moon::*;
+use notify::event::{AccessKind, AccessMode, CreateKind, ModifyKind};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use shared::{
+    BlockId, BlockMessage, BlockOrder, DownMsg, EventChoiceMessage, EventId, EventStatus, Utterance,
+};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Filesystem notifications are the fast path; this catches anything they miss (or can't see at all,
+// like an event directory that doesn't exist yet)
+const FALLBACK_POLL: Duration = Duration::from_secs(5);
+
+// ------ ------
+//    States
+// ------ ------
+
+// The events with a watcher, one each, alive for as long as somebody is subscribed to the event
+static WATCHED: Mutex<BTreeSet<EventId>> = Mutex::new(BTreeSet::new());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Starts the event's watcher if it hasn't got one, for a session that just subscribed to it. A
+// finished event whose blocks are all in has nothing left to watch for, nor has a deleted one.
+pub fn watch(event_id: EventId) {
+    if !catalog::event_exists(event_id) {
+        return;
+    }
+    realtime::ensure_stream(event_id);
+    let was_finished = catalog::load_manifest(event_id).status == EventStatus::Finished;
+    if was_finished && is_fully_ingested(event_id) {
+        return;
+    }
+    if WATCHED.lock().unwrap().insert(event_id) {
+        println!("Starting ingestion for event {}", event_id);
+        tokio::spawn(watch_event(event_id, was_finished));
+    }
+}
+
+// An event that was already finished (say, transcribed before anybody opened it) only has its
+// blocks loaded; its subscribers' snapshots already say it's finished.
+async fn watch_event(event_id: EventId, was_finished: bool) {
+    let cor_id = CorId::new();
+    let (wake_tx, mut wake_rx) = mpsc::unbounded_channel();
+    // Dropping the watcher stops the notifications, so it lives exactly as long as this task
+    let _watcher = match watch_event_dir(event_id, wake_tx) {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            println!(
+                "Polling event {}, cannot watch its directory: {:?}",
+                event_id, err
+            );
+            None
+        }
+    };
+
+    loop {
+        if !keep_watching(event_id) {
+            println!(
+                "No sessions left for event {}, stopping ingestion",
+                event_id
+            );
+            return;
+        }
+
+        ingest_new_blocks(event_id, cor_id).await;
+
+        if was_finished {
+            WATCHED.lock().unwrap().remove(&event_id);
+            return;
+        }
+        if is_event_finished(event_id) {
+            println!("Event {} finished", event_id);
+            WATCHED.lock().unwrap().remove(&event_id);
+            catalog::set_status(event_id, EventStatus::Finished);
+            let finished = store::update_document(event_id, |document| {
+                document.update(DownMsg::EventFinished(EventChoiceMessage { id: event_id }))
+            });
+            subscriptions::broadcast(event_id, &finished, cor_id).await;
+            return;
+        }
+
+        // Wake on whichever comes first, a finished block file or the fallback poll
+        match timeout(FALLBACK_POLL, wake_rx.recv()).await {
+            Ok(Some(())) => {
+                // One write tends to come with a burst of notifications, one scan covers them all
+                while wake_rx.try_recv().is_ok() {}
+            }
+            Ok(None) => sleep(FALLBACK_POLL).await, // No watcher, we're polling
+            Err(_) => {}
+        }
+    }
+}
+
+// Loads every block file we haven't stored yet, in block order. Blocks may land out of order or
+// with gaps (a block still being written, a writer that skipped a number), so rather than waiting
+// on "the next" block we take whatever is complete and let the rest turn up on a later pass.
+async fn ingest_new_blocks(event_id: EventId, cor_id: CorId) {
+    let mut block_ids = block_ids_on_disk(event_id);
+    store::with_document(event_id, |document| {
+        block_ids.retain(|id| !document.has_file(*id));
+    });
+    block_ids.sort_unstable();
+
+    for id in block_ids {
+        // A block that doesn't parse yet is most likely still being written
+        if let Some(block) = get_transcription_results(event_id, id) {
+            println!("Loading file {:?} for event {}", id, event_id);
+            let updates = store::update_document(event_id, |document| {
+                let created = document.insert_block(id, &block)?;
+                let mut updates = vec![document.update(DownMsg::BlockCreated(created))];
+                // Clients slot new blocks in by id, once a split has put the blocks out of id
+                // order they need the real order sent along
+                if !document.is_in_id_order() {
+                    let order = document.order();
+                    updates
+                        .push(document.update(DownMsg::BlockOrder(BlockOrder { event_id, order })));
+                }
+                Some(updates)
+            });
+            for update in updates.into_iter().flatten() {
+                subscriptions::broadcast(event_id, &update, cor_id).await;
+            }
+        }
+    }
+}
+
+fn watch_event_dir(
+    event_id: EventId,
+    wake_tx: UnboundedSender<()>,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) if is_block_written(&event) => {
+            let _ = wake_tx.send(());
+        }
+        Ok(_) => {}
+        Err(err) => eprintln!("Event {} watch error: {:?}", event_id, err),
+    })?;
+    watcher.watch(&catalog::event_dir(event_id), RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+// inotify tells us when a writer closes the file (or renames a finished file into place), which
+// is what we want; other platforms only report creates and modifications, which we take too and
+// let a failed parse sort out the partially written ones.
+fn is_block_written(event: &Event) -> bool {
+    let is_block_file = event
+        .paths
+        .iter()
+        .any(|path| block_id_from_path(path).is_some());
+    let is_write = matches!(
+        event.kind,
+        EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Create(CreateKind::File)
+            | EventKind::Create(CreateKind::Any)
+            | EventKind::Modify(ModifyKind::Name(_))
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Any)
+    );
+    is_block_file && is_write
+}
+
+// When no sessions remain subscribed, the event is released so the next subscriber starts a
+// fresh watcher
+fn keep_watching(event_id: EventId) -> bool {
+    let mut watched = WATCHED.lock().unwrap();
+    if subscriptions::subscribers(event_id).is_empty() {
+        watched.remove(&event_id);
+        return false;
+    }
+    true
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// Whoever produces the block files drops this marker once the last block is written
+// (or marks the event finished in its manifest)
+fn is_event_finished(event_id: EventId) -> bool {
+    catalog::event_dir(event_id)
+        .join("__event_finished")
+        .exists()
+        || catalog::load_manifest(event_id).status == EventStatus::Finished
+}
+
+fn is_fully_ingested(event_id: EventId) -> bool {
+    let block_ids = block_ids_on_disk(event_id);
+    store::with_document(event_id, |document| {
+        block_ids.iter().all(|id| document.has_file(*id))
+    })
+}
+
+fn block_id_from_path(path: &Path) -> Option<BlockId> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_prefix("block_")?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+pub fn block_ids_on_disk(event_id: EventId) -> Vec<BlockId> {
+    match fs::read_dir(catalog::event_dir(event_id)) {
+        Ok(entries) => entries
+            .filter_map(|entry| block_id_from_path(&entry.ok()?.path()))
+            .collect(),
+        Err(_) => Vec::new(), // Nothing transcribed yet
+    }
+}
+
+fn read_user_from_file<P: AsRef<Path>>(path: P) -> Result<Utterance, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let tx = serde_json::from_reader(reader)?;
+    Ok(tx)
+}
+
+fn get_transcription_results(event_id: EventId, id: BlockId) -> Option<BlockMessage> {
+    let path = catalog::event_dir(event_id).join(format!("block_{:04}.json", id));
+    match read_user_from_file(path) {
+        Ok(block) => {
+            let speaker = block.speaker.clone().unwrap_or_else(|| "".to_string());
+            Some(BlockMessage {
+                event_id,
+                id,
+                version: 0,
+                words: block.words,
+                speaker,
+            })
+        }
+        Err(_) => {
+            // TODO: Only squash this if it is file not found (2)
+            // println!("Err kind: {:?}", err);
+            None
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::catalog::{self, EventManifest};
+use moon::*;
+use shared::speakers::{name_key, LibraryPerson, Person, PersonId, SpeakerLibrary, Suggestion};
+use shared::EventId;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const DEFAULT_LIBRARY_FILE: &str = "speakers.json";
+
+// ------ ------
+//    States
+// ------ ------
+
+// Held while changing the library file, so two editors adding people don't lose one of them
+static LIBRARY_WRITE: Mutex<()> = Mutex::new(());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// The people speakers are linked to across events, in JADILI_SPEAKER_LIBRARY or `speakers.json`
+fn library_path() -> PathBuf {
+    env::var("JADILI_SPEAKER_LIBRARY")
+        .unwrap_or_else(|_| DEFAULT_LIBRARY_FILE.to_string())
+        .into()
+}
+
+// No library file yet is simply nobody in it
+fn read_people() -> Result<Vec<Person>, String> {
+    let people: Result<Vec<Person>, String> = match File::open(library_path()) {
+        Ok(file) => serde_json::from_reader(file).map_err(|err| err.to_string()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.to_string()),
+    };
+    people.map_err(|err| {
+        eprintln!("Cannot read the speaker library: {}", err);
+        "The speaker library can't be read right now".to_string()
+    })
+}
+
+fn save_people(people: &[Person]) -> Result<(), String> {
+    let path = library_path();
+    let tmp_path = path.with_extension("json.tmp");
+    File::create(&tmp_path)
+        .map_err(|err| err.to_string())
+        .and_then(|file| serde_json::to_writer_pretty(file, people).map_err(|err| err.to_string()))
+        .and_then(|()| fs::rename(&tmp_path, &path).map_err(|err| err.to_string()))
+        .map_err(|err| {
+            eprintln!("Cannot write the speaker library: {}", err);
+            "The speaker library can't be changed right now".to_string()
+        })
+}
+
+pub fn person(id: PersonId) -> Result<Person, String> {
+    read_people()?
+        .into_iter()
+        .find(|person| person.id == id)
+        .ok_or_else(|| format!("There is nobody {} in the speaker library", id))
+}
+
+// Adds the person and hands them to `link`, holding the library the whole time. A link that fails
+// takes them back out, rather than leaving someone in the library that nothing links to.
+pub fn add_person<R>(
+    name: &str,
+    notes: Option<String>,
+    link: impl FnOnce(Person) -> Result<R, String>,
+) -> Result<R, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("A person in the speaker library needs a name".to_string());
+    }
+    let _write = LIBRARY_WRITE.lock().unwrap();
+    let mut people = read_people()?;
+    let person = Person {
+        id: people.iter().map(|person| person.id + 1).max().unwrap_or(1),
+        name: name.to_string(),
+        notes: notes.filter(|notes| !notes.trim().is_empty()),
+    };
+    people.push(person.clone());
+    save_people(&people)?;
+    match link(person.clone()) {
+        Ok(linked) => {
+            println!(
+                "{} is person {} in the speaker library",
+                person.name, person.id
+            );
+            Ok(linked)
+        }
+        Err(reason) => {
+            people.pop();
+            // Already logged if it fails, and the link's reason is the one to give
+            let _ = save_people(&people);
+            Err(reason)
+        }
+    }
+}
+
+// Counted from the event manifests every time, so the numbers can't drift from the links. An
+// event's own links count towards how often a person is used, but only the others suggest.
+pub fn library(event_id: EventId) -> Result<SpeakerLibrary, String> {
+    let people = read_people()?;
+    let manifests = catalog::list_events();
+    let mut events: BTreeMap<PersonId, usize> = BTreeMap::new();
+    for manifest in &manifests {
+        let linked: BTreeSet<PersonId> = manifest
+            .speakers
+            .values()
+            .filter_map(|profile| profile.person)
+            .collect();
+        for person_id in linked {
+            *events.entry(person_id).or_default() += 1;
+        }
+    }
+    let used = |person_id: &PersonId| events.get(person_id).copied().unwrap_or(0);
+
+    let suggestions = name_suggestions(event_id, &manifests, &people)
+        .into_iter()
+        .map(|(name, counts)| {
+            let mut suggested: Vec<Suggestion> = counts
+                .into_iter()
+                .map(|(person_id, times)| Suggestion { person_id, times })
+                .collect();
+            suggested.sort_by_key(|suggestion| {
+                (
+                    Reverse(suggestion.times),
+                    Reverse(used(&suggestion.person_id)),
+                )
+            });
+            (name, suggested)
+        })
+        .collect();
+    let mut people: Vec<LibraryPerson> = people
+        .into_iter()
+        .map(|person| LibraryPerson {
+            events: used(&person.id),
+            person,
+        })
+        .collect();
+    people.sort_by(|a, b| {
+        b.events
+            .cmp(&a.events)
+            .then_with(|| a.person.name.cmp(&b.person.name))
+    });
+    Ok(SpeakerLibrary {
+        event_id,
+        people,
+        suggestions,
+    })
+}
+
+// By speaker name, how many other events linked a speaker of that name to each person. Everyone
+// in the library is suggested for their own name too, if only 0 times.
+fn name_suggestions(
+    event_id: EventId,
+    manifests: &[EventManifest],
+    people: &[Person],
+) -> BTreeMap<String, BTreeMap<PersonId, usize>> {
+    let mut suggestions: BTreeMap<String, BTreeMap<PersonId, usize>> = BTreeMap::new();
+    for manifest in manifests.iter().filter(|manifest| manifest.id != event_id) {
+        let named: BTreeSet<(String, PersonId)> = manifest
+            .speakers
+            .values()
+            .filter_map(|profile| Some((name_key(&profile.name), profile.person?)))
+            .filter(|(name, _)| !name.is_empty())
+            .collect();
+        for (name, person_id) in named {
+            *suggestions
+                .entry(name)
+                .or_default()
+                .entry(person_id)
+                .or_default() += 1;
+        }
+    }
+    // Links to somebody no longer in the library suggest nobody
+    for counts in suggestions.values_mut() {
+        counts.retain(|person_id, _| people.iter().any(|person| person.id == *person_id));
+    }
+    suggestions.retain(|_, counts| !counts.is_empty());
+    for person in people {
+        suggestions
+            .entry(name_key(&person.name))
+            .or_default()
+            .entry(person.id)
+            .or_default();
+    }
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::speakers::SpeakerProfile;
+
+    fn person(id: PersonId, name: &str) -> Person {
+        Person {
+            id,
+            name: name.to_string(),
+            notes: None,
+        }
+    }
+
+    // `speakers` are (label, name, linked person)
+    fn event(id: EventId, speakers: &[(&str, &str, Option<PersonId>)]) -> EventManifest {
+        let speakers = speakers
+            .iter()
+            .map(|(label, name, person)| {
+                let profile = SpeakerProfile {
+                    name: name.to_string(),
+                    person: *person,
+                    ..SpeakerProfile::default()
+                };
+                (label.to_string(), profile)
+            })
+            .collect();
+        EventManifest {
+            id,
+            speakers,
+            ..EventManifest::default()
+        }
+    }
+
+    #[test]
+    fn names_suggest_whoever_they_were_linked_to_elsewhere() {
+        let people = [person(1, "Ann Jones"), person(2, "Bo Diaz")];
+        let manifests = [
+            event(
+                1,
+                &[("A", "Mayor Jones", Some(1)), ("B", "Bo Diaz", Some(2))],
+            ),
+            event(2, &[("C", "mayor  jones", Some(1))]),
+            // The event asking doesn't suggest to itself
+            event(3, &[("A", "Mayor Jones", Some(2))]),
+        ];
+        let suggestions = name_suggestions(3, &manifests, &people);
+        assert_eq!(suggestions["mayor jones"], BTreeMap::from([(1, 2)]));
+        assert_eq!(suggestions["bo diaz"], BTreeMap::from([(2, 1)]));
+        // Labels mean nothing from one event to the next
+        assert!(!suggestions.contains_key("a"));
+    }
+
+    #[test]
+    fn everyone_is_suggested_for_their_own_name() {
+        let people = [person(1, "Ann Jones")];
+        let manifests = [event(1, &[("A", "Unknown woman", None)])];
+        let suggestions = name_suggestions(2, &manifests, &people);
+        assert_eq!(
+            suggestions,
+            BTreeMap::from([("ann jones".to_string(), BTreeMap::from([(1, 0)]))])
+        );
+    }
+
+    #[test]
+    fn links_to_people_gone_from_the_library_suggest_nobody() {
+        let manifests = [event(1, &[("A", "Ann Jones", Some(9))])];
+        assert!(name_suggestions(2, &manifests, &[]).is_empty());
+    }
+}
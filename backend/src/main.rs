@@ -1,37 +1,24 @@
-use moon::tokio::time::{sleep, Duration};
+use moon::actix_web::web;
 use moon::*;
-use shared::{BlockMessage, DownMsg, EventStreamMessage, UpMsg, Utterance};
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-fn read_user_from_file<P: AsRef<Path>>(path: P) -> Result<Utterance, Box<dyn Error>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let tx = serde_json::from_reader(reader)?;
-    Ok(tx)
-}
+use shared::roles::Role;
+use shared::speakers::{Person, SpeakerProfile};
+use shared::{
+    ApprovalRejected, AuditTrail, DownMsg, EventChoiceMessage, EventId, EventRole,
+    EventStreamMessage, HistoryRejected, MergeRejected, SpeakerRejected, SpeakerRenamed, UpMsg,
+};
 
-fn get_transcription_results(event_name: String, id: usize) -> Option<BlockMessage> {
-    let path = format!("./public/assets/{}/block_{:04}.json", event_name, id);
-    match read_user_from_file(path) {
-        Ok(block) => {
-            let speaker = block.speaker.clone().unwrap_or_else(|| "".to_string());
-            Some(BlockMessage {
-                id,
-                words: block.words,
-                speaker,
-            })
-        }
-        Err(_) => {
-            // TODO: Only squash this if it is file not found (2)
-            // println!("Err kind: {:?}", err);
-            None
-        }
-    }
-}
+mod assembly_ai;
+mod audit;
+mod auth;
+mod catalog;
+mod export;
+mod ingest;
+mod library;
+mod realtime;
+mod store;
+mod subscriptions;
+mod transcription;
+mod upload;
 
 async fn frontend() -> Frontend {
     Frontend::new()
@@ -43,19 +30,277 @@ async fn frontend() -> Frontend {
 
 async fn up_msg_handler(req: UpMsgRequest<UpMsg>) {
     // println!("request: {:?}", req);
-    let UpMsgRequest { up_msg, cor_id, .. } = req;
+    let UpMsgRequest {
+        up_msg,
+        cor_id,
+        session_id,
+        auth_token,
+    } = req;
+    let token = auth_token.map(AuthToken::into_string);
+    let user = auth::authenticate(token.as_deref());
+    // A token we don't know (expired, or from before a restart) means logging in again, rather
+    // than quietly carrying on as someone who can only look
+    if token.is_some() && user.is_none() && !matches!(up_msg, UpMsg::Login(_) | UpMsg::Logout) {
+        println!("Refusing a message with an unknown or expired token");
+        send_to_session(session_id, &DownMsg::AuthRequired, cor_id).await;
+        return;
+    }
+    // Only logged in users see an event, and only those with the role for it change anything
+    if let Some((event_id, required)) = required_role(&up_msg) {
+        let user = match &user {
+            Some(user) => user,
+            None => {
+                println!("Refusing a message from a session that isn't logged in");
+                send_to_session(session_id, &DownMsg::AuthRequired, cor_id).await;
+                return;
+            }
+        };
+        let role = auth::role(user, event_id);
+        if role < required {
+            println!("Refusing {:?} from {}, a {}", up_msg, user, role.name());
+            let reason = format!("You need to be a {} to do that", required.name());
+            send_to_session(session_id, &DownMsg::PermissionDenied(reason), cor_id).await;
+            return;
+        }
+    }
+    let user = user.unwrap_or_default();
 
     match up_msg {
+        UpMsg::Login(login) => {
+            let reply = match auth::log_in(&login.username, &login.password) {
+                Ok(accepted) => DownMsg::LoggedIn(accepted),
+                Err(reason) => DownMsg::LoginRejected(reason),
+            };
+            send_to_session(session_id, &reply, cor_id).await;
+        }
+        UpMsg::Logout => {
+            if let Some(token) = token {
+                auth::log_out(&token);
+            }
+        }
+        UpMsg::ListEvents => {
+            let events = catalog::list_events()
+                .iter()
+                .map(|manifest| manifest.to_summary())
+                .collect();
+            send_to_session(session_id, &DownMsg::EventList(events), cor_id).await;
+        }
         UpMsg::DeleteBlock(block) => {
             println!("Delete Block {:?}", block.id);
-            sessions::broadcast_down_msg(&DownMsg::BlockDeleted(block), cor_id).await;
+            let deleted = store::update_document(block.event_id, |document| {
+                document
+                    .delete_block(&user, block.id)
+                    .then(|| document.update(DownMsg::BlockDeleted(block.clone())))
+            });
+            if let Some(deleted) = deleted {
+                subscriptions::broadcast(block.event_id, &deleted, cor_id).await;
+            }
+        }
+        UpMsg::EditWords(edit) => {
+            println!("Edit words of block {:?}: {:?}", edit.id, edit.ops);
+            match store::update_document(edit.event_id, |document| {
+                document
+                    .edit_words(&user, &edit)
+                    .map(|applied| document.update(DownMsg::WordsEdited(applied)))
+            }) {
+                Ok(applied) => subscriptions::broadcast(edit.event_id, &applied, cor_id).await,
+                // Somebody got there first, only the editor needs to hear about it
+                Err(conflict) => {
+                    println!("Edit of block {:?} conflicts, rejecting it", conflict.id);
+                    send_to_session(session_id, &DownMsg::EditConflict(conflict), cor_id).await;
+                }
+            }
+        }
+        UpMsg::MergeBlockAbove(merge) => {
+            println!("Merge Block {:?} above", merge.id);
+            // Every client takes the merged block as we hold it, rather than working it out itself
+            match store::update_document(merge.event_id, |document| {
+                document
+                    .merge_block_above(&user, &merge)
+                    .map(|merged| document.update(DownMsg::BlockMergedWithAbove(merged)))
+            }) {
+                Ok(merged) => subscriptions::broadcast(merge.event_id, &merged, cor_id).await,
+                Err(reason) => {
+                    println!("Cannot merge block {:?}: {}", merge.id, reason);
+                    let rejected = MergeRejected {
+                        event_id: merge.event_id,
+                        id: merge.id,
+                        reason,
+                    };
+                    send_to_session(session_id, &DownMsg::MergeRejected(rejected), cor_id).await;
+                }
+            }
+        }
+        UpMsg::SplitBlock(split) => {
+            println!("Split block {:?} at word {}", split.id, split.at_word_index);
+            match store::update_document(split.event_id, |document| {
+                document
+                    .split_block(&user, &split)
+                    .map(|block_split| document.update(DownMsg::BlockSplit(block_split)))
+            }) {
+                Ok(block_split) => {
+                    subscriptions::broadcast(split.event_id, &block_split, cor_id).await;
+                }
+                // The block changed under the editor, they need to pick the word again
+                Err(conflict) => {
+                    println!("Split of block {:?} is stale, rejecting it", conflict.id);
+                    send_to_session(session_id, &DownMsg::EditConflict(conflict), cor_id).await;
+                }
+            }
+        }
+        UpMsg::Undo(event) => {
+            println!("Undo in event {}", event.id);
+            let restored = store::update_document(event.id, |document| {
+                let restored = document.undo(&user)?;
+                Ok(document.update(DownMsg::BlocksRestored(restored)))
+            });
+            history_reply(event.id, restored, session_id, cor_id).await;
+        }
+        UpMsg::Redo(event) => {
+            println!("Redo in event {}", event.id);
+            let restored = store::update_document(event.id, |document| {
+                let restored = document.redo(&user)?;
+                Ok(document.update(DownMsg::BlocksRestored(restored)))
+            });
+            history_reply(event.id, restored, session_id, cor_id).await;
+        }
+        UpMsg::ListAudit(query) => {
+            let trail = AuditTrail {
+                event_id: query.event_id,
+                block_id: query.block_id,
+                entries: audit::entries(query.event_id, query.block_id),
+            };
+            send_to_session(session_id, &DownMsg::AuditTrail(trail), cor_id).await;
+        }
+        UpMsg::RestoreBlock(restore) => {
+            println!(
+                "Restore block {:?} to audit entry {}",
+                restore.id, restore.seq
+            );
+            let restored = match audit::entry(restore.event_id, restore.seq) {
+                Some(entry) if entry.block_id == restore.id => {
+                    store::update_document(restore.event_id, |document| {
+                        let restored = document.restore_block(&user, restore.id, &entry.after)?;
+                        Ok(document.update(DownMsg::BlocksRestored(restored)))
+                    })
+                }
+                _ => Err(format!(
+                    "Block {} has no audit entry {}",
+                    restore.id, restore.seq
+                )),
+            };
+            history_reply(restore.event_id, restored, session_id, cor_id).await;
+        }
+        UpMsg::ApproveBlock(approval) => {
+            println!(
+                "Approve block {:?} at version {}",
+                approval.id, approval.version
+            );
+            match store::update_document(approval.event_id, |document| {
+                document
+                    .approve_block(&user, &approval)
+                    .map(|approved| document.update(DownMsg::BlockApproved(approved)))
+            }) {
+                Ok(approved) => {
+                    subscriptions::broadcast(approval.event_id, &approved, cor_id).await;
+                }
+                Err(reason) => {
+                    println!("Cannot approve block {:?}: {}", approval.id, reason);
+                    let rejected = ApprovalRejected {
+                        event_id: approval.event_id,
+                        id: approval.id,
+                        reason,
+                    };
+                    send_to_session(session_id, &DownMsg::ApprovalRejected(rejected), cor_id).await;
+                }
+            }
+        }
+        UpMsg::RenameSpeaker(renamed) => {
+            println!(
+                "Speaker {} of event {} is now {:?}",
+                renamed.label, renamed.event_id, renamed.profile
+            );
+            // The library link stays, only LinkSpeaker changes it
+            let update =
+                rename_speaker(renamed.event_id, &renamed.label, |profile| SpeakerProfile {
+                    person: profile.person,
+                    ..renamed.profile
+                });
+            speaker_reply(renamed.event_id, update, session_id, cor_id).await;
+        }
+        UpMsg::ReassignBlockSpeaker(reassign) => {
+            println!(
+                "Reassign block {:?} to speaker {}",
+                reassign.id, reassign.speaker
+            );
+            let update = store::update_document(reassign.event_id, |document| {
+                let block = document.reassign_speaker(&user, &reassign)?;
+                Ok(document.update(DownMsg::BlockSpeakerReassigned(block)))
+            });
+            speaker_reply(reassign.event_id, update, session_id, cor_id).await;
+        }
+        UpMsg::ListSpeakerLibrary(event) => {
+            send_library(event.id, session_id, cor_id).await;
+        }
+        UpMsg::AddPerson(new) => {
+            println!("{} adds {} to the speaker library", user, new.name);
+            let update = library::add_person(&new.name, new.notes, |person| {
+                link_speaker(new.event_id, &new.label, Some(person))
+            });
+            speaker_reply(new.event_id, update, session_id, cor_id).await;
+            send_library(new.event_id, session_id, cor_id).await;
+        }
+        UpMsg::LinkSpeaker(link) => {
+            println!(
+                "Link speaker {} of event {} to {:?}",
+                link.label, link.event_id, link.person_id
+            );
+            let update = link
+                .person_id
+                .map(library::person)
+                .transpose()
+                .and_then(|person| link_speaker(link.event_id, &link.label, person));
+            speaker_reply(link.event_id, update, session_id, cor_id).await;
+            send_library(link.event_id, session_id, cor_id).await;
+        }
+        UpMsg::ListAccounts => {
+            let reply = match auth::account_summaries() {
+                Ok(accounts) => DownMsg::Accounts(accounts),
+                Err(reason) => DownMsg::PermissionDenied(reason),
+            };
+            send_to_session(session_id, &reply, cor_id).await;
         }
-        UpMsg::EditBlock(block) => {
-            println!("Edit Block {:?}", block.id);
-            sessions::broadcast_down_msg(&DownMsg::BlockEdited(block), cor_id).await;
+        UpMsg::SetRole(assignment) => {
+            println!(
+                "{} sets {}'s role in {:?} to {:?}",
+                user, assignment.username, assignment.event_id, assignment.role
+            );
+            let reply = match auth::set_role(&assignment) {
+                Ok(accounts) => DownMsg::Accounts(accounts),
+                Err(reason) => DownMsg::PermissionDenied(reason),
+            };
+            send_to_session(session_id, &reply, cor_id).await;
         }
-        UpMsg::MergeBlockAbove(block) => {
-            sessions::broadcast_down_msg(&DownMsg::BlockMergedWithAbove(block), cor_id).await;
+        UpMsg::DeleteEvent(event) => {
+            println!("{} deletes event {}", user, event.id);
+            if let Err(err) = catalog::delete_event(event.id) {
+                eprintln!("Failed to delete event {}: {:?}", event.id, err);
+                let reason = format!("Event {} can't be deleted: {}", event.id, err);
+                send_to_session(session_id, &DownMsg::PermissionDenied(reason), cor_id).await;
+                return;
+            }
+            store::forget_document(event.id);
+            audit::forget(event.id);
+            // Its watcher stops once it has nobody left to watch for
+            let deleted = DownMsg::EventDeleted(EventChoiceMessage { id: event.id });
+            subscriptions::broadcast(event.id, &deleted, cor_id).await;
+            subscriptions::drop_event(event.id);
+
+            let events = catalog::list_events()
+                .iter()
+                .map(|manifest| manifest.to_summary())
+                .collect();
+            sessions::broadcast_down_msg(&DownMsg::EventList(events), cor_id).await;
         }
         UpMsg::ChooseEvent(event) => {
             println!("Choose Event {}", event.id);
@@ -63,31 +308,203 @@ async fn up_msg_handler(req: UpMsgRequest<UpMsg>) {
                 id: event.id,
                 data: format!("Selected event {}", event.id),
             };
-            let event_name = format!("event_{:04}", event.id);
-
-            sessions::broadcast_down_msg(&DownMsg::EventSelected(stream), cor_id).await;
-
-            static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
-            tokio::spawn(async move {
-                loop {
-                    // We may not have the next file on disk, no worries, sleep and come back later
-                    let id = NEXT_ID.load(Ordering::SeqCst);
-                    if let Some(block) = get_transcription_results(
-                        event_name.clone(),
-                        NEXT_ID.load(Ordering::SeqCst),
-                    ) {
-                        println!("Loading file {:?}", id);
-                        sessions::broadcast_down_msg(&DownMsg::BlockCreated(block), cor_id).await;
-                        NEXT_ID.store(id + 1, Ordering::SeqCst);
-                    }
-                    sleep(Duration::from_millis(500)).await; // TODO: tighten this up once it's working
-                }
-            });
+            let event_id = event.id;
+
+            send_to_session(session_id, &DownMsg::EventSelected(stream), cor_id).await;
+
+            // So the chooser's page only offers what they may do. Nobody logged in only looks.
+            let role = if user.is_empty() {
+                Role::Viewer
+            } else {
+                auth::role(&user, Some(event_id))
+            };
+            let event_role = DownMsg::EventRole(EventRole { event_id, role });
+            send_to_session(session_id, &event_role, cor_id).await;
+
+            // Subscribed before the snapshot is taken, so nothing slips in between. The updates
+            // it already includes are numbered no higher than it, the client skips those.
+            subscriptions::subscribe(event_id, session_id);
+            ingest::watch(event_id);
+            send_snapshot(event_id, session_id, cor_id).await;
+        }
+        UpMsg::Resync(event) => {
+            println!("Resync event {}", event.id);
+            send_snapshot(event.id, session_id, cor_id).await;
         }
     }
 }
 
+// The role a message needs, and the event it needs it in (None for account-wide ones). None for
+// messages anyone can send.
+fn required_role(up_msg: &UpMsg) -> Option<(Option<EventId>, Role)> {
+    let (event_id, role) = match up_msg {
+        UpMsg::ChooseEvent(event) | UpMsg::Resync(event) => (Some(event.id), Role::Viewer),
+        UpMsg::ListAudit(query) => (Some(query.event_id), Role::Viewer),
+        UpMsg::EditWords(edit) => (Some(edit.event_id), Role::Editor),
+        UpMsg::DeleteBlock(block) => (Some(block.event_id), Role::Editor),
+        UpMsg::MergeBlockAbove(merge) => (Some(merge.event_id), Role::Editor),
+        UpMsg::SplitBlock(split) => (Some(split.event_id), Role::Editor),
+        UpMsg::Undo(event) | UpMsg::Redo(event) => (Some(event.id), Role::Editor),
+        UpMsg::RestoreBlock(restore) => (Some(restore.event_id), Role::Editor),
+        UpMsg::RenameSpeaker(renamed) => (Some(renamed.event_id), Role::Editor),
+        UpMsg::ReassignBlockSpeaker(reassign) => (Some(reassign.event_id), Role::Editor),
+        UpMsg::ListSpeakerLibrary(event) => (Some(event.id), Role::Editor),
+        UpMsg::AddPerson(new) => (Some(new.event_id), Role::Editor),
+        UpMsg::LinkSpeaker(link) => (Some(link.event_id), Role::Editor),
+        UpMsg::ApproveBlock(approval) => (Some(approval.event_id), Role::Reviewer),
+        UpMsg::ListAccounts | UpMsg::SetRole(_) | UpMsg::DeleteEvent(_) => (None, Role::Admin),
+        UpMsg::Login(_) | UpMsg::Logout | UpMsg::ListEvents => return None,
+    };
+    Some((event_id, role))
+}
+
+// Everyone sees the blocks come back, only the user who asked hears why they couldn't
+async fn history_reply(
+    event_id: EventId,
+    restored: Result<DownMsg, String>,
+    session_id: SessionId,
+    cor_id: CorId,
+) {
+    match restored {
+        Ok(restored) => subscriptions::broadcast(event_id, &restored, cor_id).await,
+        Err(reason) => {
+            println!("Event {}: {}", event_id, reason);
+            let rejected = DownMsg::HistoryRejected(HistoryRejected { event_id, reason });
+            send_to_session(session_id, &rejected, cor_id).await;
+        }
+    }
+}
+
+// Saves the label's new profile, worked out from the one it has. Numbered with the block updates,
+// so a snapshot has the new profile or comes before it.
+fn rename_speaker(
+    event_id: EventId,
+    label: &str,
+    change: impl FnOnce(SpeakerProfile) -> SpeakerProfile,
+) -> Result<DownMsg, String> {
+    store::update_document(event_id, |document| {
+        let profile = catalog::update_speaker_profile(event_id, label, change).map_err(|err| {
+            eprintln!("Failed to save speaker {}: {:?}", label, err);
+            "The speaker can't be renamed right now".to_string()
+        })?;
+        Ok(document.update(DownMsg::SpeakerRenamed(SpeakerRenamed {
+            event_id,
+            label: label.to_string(),
+            profile,
+        })))
+    })
+}
+
+// Named after the person, and keeping its role and color. Unlinking keeps the name.
+fn link_speaker(event_id: EventId, label: &str, person: Option<Person>) -> Result<DownMsg, String> {
+    rename_speaker(event_id, label, |profile| match person {
+        Some(person) => SpeakerProfile {
+            name: person.name,
+            person: Some(person.id),
+            ..profile
+        },
+        None => SpeakerProfile {
+            person: None,
+            ..profile
+        },
+    })
+}
+
+async fn speaker_reply(
+    event_id: EventId,
+    update: Result<DownMsg, String>,
+    session_id: SessionId,
+    cor_id: CorId,
+) {
+    match update {
+        Ok(update) => subscriptions::broadcast(event_id, &update, cor_id).await,
+        Err(reason) => {
+            println!("Event {}: {}", event_id, reason);
+            let rejected = DownMsg::SpeakerRejected(SpeakerRejected { event_id, reason });
+            send_to_session(session_id, &rejected, cor_id).await;
+        }
+    }
+}
+
+// Everything in the event so far, corrections and speakers included
+async fn send_snapshot(event_id: EventId, session_id: SessionId, cor_id: CorId) {
+    // Read under the document's lock, like a rename writes it
+    let snapshot = store::with_document(event_id, |document| {
+        document.snapshot(&catalog::load_manifest(event_id))
+    });
+    send_to_session(session_id, &DownMsg::EventSnapshot(snapshot), cor_id).await;
+}
+
+// Linking changes the counts, so whoever linked gets the library again
+async fn send_library(event_id: EventId, session_id: SessionId, cor_id: CorId) {
+    let reply = match library::library(event_id) {
+        Ok(library) => DownMsg::SpeakerLibrary(library),
+        Err(reason) => DownMsg::SpeakerRejected(SpeakerRejected { event_id, reason }),
+    };
+    send_to_session(session_id, &reply, cor_id).await;
+}
+
+async fn send_to_session(session_id: SessionId, down_msg: &DownMsg, cor_id: CorId) {
+    match sessions::by_session_id().wait_for(session_id).await {
+        Some(session) => session.send_down_msg(down_msg, cor_id).await,
+        None => println!("Cannot find the session with id `{}`", session_id),
+    }
+}
+
 #[moon::main]
 async fn main() -> std::io::Result<()> {
-    start(frontend, up_msg_handler, |_| {}).await
+    transcription::resume_jobs();
+    realtime::resume_streams();
+    start(frontend, up_msg_handler, |cfg| {
+        cfg.route("/upload_event", web::post().to(upload::upload_event))
+            .route(
+                "/export/{event_id}/{format}",
+                web::get().to(export::export_event),
+            );
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{AuditQuery, BlockApproval};
+
+    #[test]
+    fn reading_an_event_takes_a_login() {
+        let event = EventChoiceMessage { id: 3 };
+        assert_eq!(
+            required_role(&UpMsg::ChooseEvent(event.clone())),
+            Some((Some(3), Role::Viewer))
+        );
+        assert_eq!(
+            required_role(&UpMsg::Resync(event)),
+            Some((Some(3), Role::Viewer))
+        );
+        let audit = UpMsg::ListAudit(AuditQuery {
+            event_id: 3,
+            block_id: None,
+        });
+        assert_eq!(required_role(&audit), Some((Some(3), Role::Viewer)));
+    }
+
+    #[test]
+    fn changes_take_the_role_for_them_on_their_event() {
+        let undo = UpMsg::Undo(EventChoiceMessage { id: 3 });
+        assert_eq!(required_role(&undo), Some((Some(3), Role::Editor)));
+        let approval = UpMsg::ApproveBlock(BlockApproval {
+            event_id: 3,
+            id: 7,
+            version: 2,
+        });
+        assert_eq!(required_role(&approval), Some((Some(3), Role::Reviewer)));
+        let delete = UpMsg::DeleteEvent(EventChoiceMessage { id: 3 });
+        assert_eq!(required_role(&delete), Some((None, Role::Admin)));
+    }
+
+    #[test]
+    fn logging_in_and_listing_events_take_nothing() {
+        assert_eq!(required_role(&UpMsg::Logout), None);
+        assert_eq!(required_role(&UpMsg::ListEvents), None);
+    }
 }
@@ -0,0 +1,263 @@
+use crate::{assembly_ai, catalog, ingest, store, subscriptions};
+use moon::futures::StreamExt;
+use moon::*;
+use shared::split::write_block_files;
+use shared::{BlockId, BlockMessage, DownMsg, EventId, EventStatus, Utterance};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::File;
+use std::sync::Mutex;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+
+// ------ ------
+//     Types
+// ------ ------
+
+// https://www.assemblyai.com/docs/walkthroughs#realtime-streaming-transcription
+// Partial and final transcripts are shaped just like our `Utterance`, minus the speaker
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde", tag = "message_type")]
+enum RealtimeMessage {
+    SessionBegins {
+        session_id: String,
+    },
+    PartialTranscript(Utterance),
+    FinalTranscript(Utterance),
+    SessionTerminated,
+    #[serde(other)]
+    Unknown,
+}
+
+// ------ ------
+//    States
+// ------ ------
+
+// One stream per event, however many sessions are watching it (or none at all)
+static STREAMS: Mutex<BTreeSet<EventId>> = Mutex::new(BTreeSet::new());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Start consuming the event's real-time stream, if its manifest names one and it isn't over yet
+pub fn ensure_stream(event_id: EventId) {
+    let manifest = catalog::load_manifest(event_id);
+    let url = match manifest.realtime_url {
+        Some(url) if manifest.status != EventStatus::Finished => url,
+        _ => return,
+    };
+    if !STREAMS.lock().unwrap().insert(event_id) {
+        return; // Already streaming
+    }
+    tokio::spawn(async move {
+        if let Err(err) = stream_event(event_id, &url).await {
+            eprintln!("Real-time stream for event {} failed: {}", event_id, err);
+            catalog::set_status(event_id, EventStatus::Error);
+        }
+        STREAMS.lock().unwrap().remove(&event_id);
+    });
+}
+
+// Pick the live events back up after a restart
+pub fn resume_streams() {
+    for manifest in catalog::list_events() {
+        ensure_stream(manifest.id);
+    }
+}
+
+// Partials only ever go to the editors, as the block they will become, under the id the store
+// holds for it. Finals are written as block files like any other transcript, so the ingestion
+// stores and announces them.
+async fn stream_event(event_id: EventId, url: &str) -> Result<(), Box<dyn Error>> {
+    let mut request = url.into_client_request()?;
+    if let Some(auth_key) = assembly_ai::auth_key() {
+        let mut auth_value = HeaderValue::from_str(auth_key.trim())?;
+        auth_value.set_sensitive(true);
+        request.headers_mut().insert("Authorization", auth_value);
+    }
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+    println!("Streaming event {} from {}", event_id, url);
+    catalog::set_status(event_id, EventStatus::Live);
+
+    let event_dir = catalog::event_dir(event_id);
+    let cor_id = CorId::new();
+    let mut next_id = next_block_id(event_id);
+    while let Some(message) = socket.next().await {
+        if !catalog::event_exists(event_id) {
+            println!("Event {} was deleted, dropping its stream", event_id);
+            return Ok(());
+        }
+        let text = match message? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        match serde_json::from_str(&text) {
+            Ok(RealtimeMessage::SessionBegins { session_id }) => {
+                println!("Event {} real-time session {}", event_id, session_id);
+            }
+            // Silence comes through as empty transcripts, nothing to show for it
+            Ok(RealtimeMessage::PartialTranscript(utterance)) if !utterance.text.is_empty() => {
+                let id =
+                    store::update_document(event_id, |document| document.reserve_block_id(next_id));
+                let block = BlockMessage {
+                    event_id,
+                    id,
+                    version: 0,
+                    speaker: utterance.speaker.unwrap_or_default(),
+                    words: utterance.words,
+                };
+                subscriptions::broadcast(event_id, &DownMsg::BlockPartial(block), cor_id).await;
+            }
+            Ok(RealtimeMessage::FinalTranscript(utterance)) if !utterance.text.is_empty() => {
+                write_block_files(&event_dir, &[utterance], next_id)?;
+                next_id += 1;
+            }
+            Ok(RealtimeMessage::SessionTerminated) => break,
+            Ok(_) => {}
+            Err(err) => eprintln!("Event {} unexpected message {:?}: {}", event_id, text, err),
+        }
+    }
+
+    println!("Real-time stream for event {} ended", event_id);
+    File::create(event_dir.join("__event_finished"))?;
+    catalog::set_status(event_id, EventStatus::Finished);
+    Ok(())
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// Carry on after whatever blocks an earlier (interrupted) stream left behind
+fn next_block_id(event_id: EventId) -> BlockId {
+    ingest::block_ids_on_disk(event_id)
+        .into_iter()
+        .max()
+        .map_or(1, |id| id + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moon::futures::SinkExt;
+    use moon::tokio::net::TcpListener;
+    use moon::tokio::sync::oneshot;
+    use moon::tokio::time::{sleep, Duration};
+    use shared::SplitBlockMessage;
+    use std::fs;
+    use std::future::Future;
+
+    // Two blocks' worth of a real session: partials that grow, then the final, for each
+    const SESSION: &str = include_str!("../fixtures/realtime_session.jsonl");
+    // The session up to and including the second block's first partial
+    const FIRST_BLOCK_DONE: usize = 6;
+
+    // Like `research replay`, plays the session to whoever connects, but stops after `pause_at`
+    // lines until told to carry on
+    async fn replay_server(pause_at: usize, resume: oneshot::Receiver<()>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut resume = Some(resume);
+            for (idx, line) in SESSION.lines().enumerate() {
+                if idx == pause_at {
+                    resume.take().unwrap().await.unwrap();
+                }
+                socket.send(Message::Text(line.to_string())).await.unwrap();
+            }
+            let _ = socket.close(None).await;
+        });
+        url
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Gave up waiting on the stream");
+    }
+
+    // What the ingestion would make of a block file
+    fn block_file(event_id: EventId, file: usize) -> BlockMessage {
+        let path = catalog::event_dir(event_id).join(format!("block_{:04}.json", file));
+        let utterance: Utterance =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        BlockMessage {
+            event_id,
+            id: file,
+            version: 0,
+            speaker: utterance.speaker.unwrap_or_default(),
+            words: utterance.words,
+        }
+    }
+
+    #[test]
+    fn a_replayed_session_keeps_the_ids_its_partials_were_shown_with() {
+        let event_id = 71;
+        let event_dir = catalog::event_dir(event_id);
+        let _ = fs::remove_dir_all(&event_dir);
+        fs::create_dir_all(&event_dir).unwrap();
+
+        block_on(async {
+            let (resume_tx, resume_rx) = oneshot::channel();
+            let url = replay_server(FIRST_BLOCK_DONE, resume_rx).await;
+            let editor = async {
+                // The first block is final and the second is showing as a partial, when
+                // somebody splits the first
+                wait_until(|| {
+                    store::with_document(event_id, |document| {
+                        document.reserved_ids.contains_key(&2)
+                    })
+                })
+                .await;
+                let split = store::update_document(event_id, |document| {
+                    document.insert_block(1, &block_file(event_id, 1));
+                    document.split_block(
+                        "ann",
+                        &SplitBlockMessage {
+                            event_id,
+                            id: 1,
+                            version: 0,
+                            at_word_index: 2,
+                            new_speaker: None,
+                        },
+                    )
+                });
+                assert_eq!(split.unwrap().new_block.id, 3);
+                resume_tx.send(()).unwrap();
+            };
+            let (streamed, ()) = tokio::join!(stream_event(event_id, &url), editor);
+            streamed.unwrap();
+        });
+
+        let order = store::update_document(event_id, |document| {
+            let inserted = document.insert_block(2, &block_file(event_id, 2)).unwrap();
+            assert_eq!(inserted.id, 2);
+            document.order()
+        });
+        assert_eq!(order, vec![1, 3, 2]);
+        assert_eq!(block_file(event_id, 2).words.len(), 2);
+        assert!(!event_dir.join("block_0003.json").exists());
+        assert!(event_dir.join("__event_finished").exists());
+        assert_eq!(
+            catalog::load_manifest(event_id).status,
+            EventStatus::Finished
+        );
+    }
+}
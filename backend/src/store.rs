@@ -0,0 +1,1102 @@
+use crate::audit;
+use crate::catalog::{self, EventManifest};
+use moon::*;
+use shared::align::align_words;
+use shared::audit::{AuditAction, AuditEntry, BlockSnapshot};
+use shared::ops::{apply_ops, transform_ops, WordOp};
+use shared::{
+    BlockApproval, BlockApproved, BlockId, BlockMerged, BlockMessage, BlockSplit, BlocksRestored,
+    DownMsg, EditConflict, EventId, EventSnapshot, EventStatus, EventUpdate, MergeBlockMessage,
+    ReassignBlockSpeaker, SplitBlockMessage, Word, WordsEdited,
+};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How far back a stale word edit can be rebased, in versions; anything older is a conflict
+const MAX_RECENT_EDITS: usize = 50;
+// How many of their own block operations each user can take back
+const MAX_UNDO: usize = 50;
+
+// ------ ------
+//     Types
+// ------ ------
+
+// A block as the backend knows it: the words we ingested, plus whatever the humans did to it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct StoredBlock {
+    pub id: BlockId,
+    pub speaker: String,
+    pub words: Vec<Word>, // As the block reads now, realigned to the corrected text after an edit
+    #[serde(default)]
+    pub original_words: Vec<Word>, // As transcribed; every edit is aligned against these
+    pub corrected_text: Option<String>,
+    pub is_deleted: bool,
+    pub merged_into: Option<BlockId>,
+    // The block_NNNN.json it came from, shared by both halves of a split
+    #[serde(default)]
+    pub source_file: Option<usize>,
+    #[serde(default)]
+    pub version: u64,
+    // The version a reviewer last approved, and who. Any change since means it isn't approved.
+    #[serde(default)]
+    pub approved_version: Option<u64>,
+    #[serde(default)]
+    pub approved_by: Option<String>,
+    // The ops behind the latest versions, oldest first, for rebasing edits made against them.
+    // Not kept across restarts: an edit from before one is simply a conflict.
+    #[serde(skip)]
+    recent_edits: Vec<Vec<WordOp>>,
+}
+
+impl StoredBlock {
+    pub fn is_visible(&self) -> bool {
+        !self.is_deleted && self.merged_into.is_none()
+    }
+
+    // Corrected text if a human touched this block, otherwise the space-delimited raw words
+    pub fn text(&self) -> String {
+        match &self.corrected_text {
+            Some(text) => text.clone(),
+            None => self
+                .words
+                .iter()
+                .map(|w| w.text.clone())
+                .collect::<Vec<String>>()
+                .join(" "),
+        }
+    }
+
+    // Carry the original timings over to the corrected text, so captions and playback stay in sync
+    fn realign(&mut self) {
+        if let Some(corrected_text) = &self.corrected_text {
+            self.words = align_words(&self.original_words, corrected_text);
+        }
+    }
+
+    // A new version of the block. Changes that aren't word ops (a whole new text) can't be
+    // rebased over, so they forget the recent edits.
+    fn bump_version(&mut self, ops: Option<Vec<WordOp>>) {
+        self.version += 1;
+        match ops {
+            Some(ops) => {
+                self.recent_edits.push(ops);
+                if self.recent_edits.len() > MAX_RECENT_EDITS {
+                    self.recent_edits.remove(0);
+                }
+            }
+            None => self.recent_edits.clear(),
+        }
+    }
+
+    // Every op applied since `base_version`, if we still have them all
+    fn ops_since(&self, base_version: u64) -> Option<Vec<WordOp>> {
+        let missing = self.version.checked_sub(base_version)? as usize;
+        let first = self.recent_edits.len().checked_sub(missing)?;
+        Some(self.recent_edits[first..].concat())
+    }
+
+    pub fn to_message(&self, event_id: EventId) -> BlockMessage {
+        BlockMessage {
+            event_id,
+            id: self.id,
+            version: self.version,
+            speaker: self.speaker.clone(),
+            words: self.words.clone(),
+        }
+    }
+
+    // The approval of the block as it reads now, if it has one
+    pub fn approval(&self, event_id: EventId) -> Option<BlockApproved> {
+        if self.approved_version != Some(self.version) {
+            return None;
+        }
+        Some(BlockApproved {
+            event_id,
+            id: self.id,
+            version: self.version,
+            approved_by: self.approved_by.clone()?,
+        })
+    }
+
+    fn snapshot(&self) -> BlockSnapshot {
+        BlockSnapshot {
+            version: self.version,
+            speaker: self.speaker.clone(),
+            text: self.text(),
+            is_visible: self.is_visible(),
+        }
+    }
+}
+
+// A block just before and just after an operation. Blocks are never dropped from the document,
+// so undoing a split only has to hide the half it made.
+#[derive(Clone, Debug)]
+struct BlockChange {
+    before: StoredBlock,
+    after: StoredBlock,
+}
+
+// A delete, merge, split, restore or speaker reassignment, as the blocks it changed
+#[derive(Clone, Debug)]
+struct Operation {
+    changes: Vec<BlockChange>,
+}
+
+// One user's operations on one event, most recent last
+#[derive(Default, Debug)]
+struct History {
+    undo: Vec<Operation>,
+    redo: Vec<Operation>,
+}
+
+// Everything we know about one event, in display order
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "serde")]
+pub struct EventDocument {
+    pub event_id: EventId,
+    pub blocks: Vec<StoredBlock>,
+    // The latest update's number. Kept across restarts, so clients never see the numbers go back.
+    #[serde(default)]
+    pub seq: u64,
+    // Keyed by user. Like the recent edits, gone after a restart.
+    #[serde(skip)]
+    histories: BTreeMap<String, History>,
+    // Audit entries for the changes since the document was last saved, written out with it
+    #[serde(skip)]
+    unlogged: Vec<AuditEntry>,
+    // Block files not written yet whose ids the editors already know, from a live event's
+    // partial blocks. Like the partials, gone after a restart.
+    #[serde(skip)]
+    pub reserved_ids: BTreeMap<usize, BlockId>,
+}
+
+impl EventDocument {
+    fn new(event_id: EventId) -> Self {
+        Self {
+            event_id,
+            blocks: Vec::new(),
+            seq: 0,
+            histories: BTreeMap::new(),
+            unlogged: Vec::new(),
+            reserved_ids: BTreeMap::new(),
+        }
+    }
+
+    pub fn contains(&self, id: BlockId) -> bool {
+        self.blocks.iter().any(|block| block.id == id)
+    }
+
+    pub fn has_file(&self, file: usize) -> bool {
+        self.blocks
+            .iter()
+            .any(|block| block.source_file == Some(file))
+    }
+
+    // Ids normally follow the block files, blocks we make ourselves (splits) take the next free one
+    fn next_block_id(&self) -> BlockId {
+        self.blocks
+            .iter()
+            .map(|block| block.id)
+            .chain(self.reserved_ids.values().copied())
+            .max()
+            .map_or(1, |id| id + 1)
+    }
+
+    // `id` if nothing has it (or is going to), otherwise the next free one
+    fn free_block_id(&self, id: BlockId) -> BlockId {
+        if self.contains(id) || self.reserved_ids.values().any(|reserved| *reserved == id) {
+            self.next_block_id()
+        } else {
+            id
+        }
+    }
+
+    // The id block file `file` will be stored under, held for it until it is. A partial block is
+    // shown with it, so a split meanwhile can't leave the final block with another.
+    pub fn reserve_block_id(&mut self, file: usize) -> BlockId {
+        if let Some(id) = self.reserved_ids.get(&file) {
+            return *id;
+        }
+        let id = self.free_block_id(file);
+        self.reserved_ids.insert(file, id);
+        id
+    }
+
+    // The visible blocks' ids, top to bottom
+    pub fn order(&self) -> Vec<BlockId> {
+        self.blocks
+            .iter()
+            .filter(|block| block.is_visible())
+            .map(|block| block.id)
+            .collect()
+    }
+
+    // Clients slot new blocks in by id, which is only right until a split puts a newer id
+    // between older ones
+    pub fn is_in_id_order(&self) -> bool {
+        self.blocks.windows(2).all(|pair| pair[0].id < pair[1].id)
+    }
+
+    fn position(&self, id: BlockId) -> Option<usize> {
+        self.blocks.iter().position(|block| block.id == id)
+    }
+
+    // Stores the block read from block file `file`, returning it as stored (its id is the one
+    // reserved for the file, or else the file's number unless a split already took that). None if
+    // we've already seen this file (e.g. re-reading the files after a restart).
+    pub fn insert_block(&mut self, file: usize, block: &BlockMessage) -> Option<BlockMessage> {
+        if self.has_file(file) {
+            return None;
+        }
+        let id = match self.reserved_ids.remove(&file) {
+            Some(id) => id,
+            None => self.free_block_id(block.id),
+        };
+        // Block files can turn up out of order, keep the document in file order regardless
+        let idx = self
+            .blocks
+            .iter()
+            .position(|stored| stored.source_file.map_or(false, |source| source > file))
+            .unwrap_or(self.blocks.len());
+        self.blocks.insert(
+            idx,
+            StoredBlock {
+                id,
+                speaker: block.speaker.clone(),
+                words: block.words.clone(),
+                original_words: block.words.clone(),
+                corrected_text: None,
+                is_deleted: false,
+                merged_into: None,
+                source_file: Some(file),
+                version: 0,
+                approved_version: None,
+                approved_by: None,
+                recent_edits: Vec::new(),
+            },
+        );
+        Some(self.blocks[idx].to_message(self.event_id))
+    }
+
+    // The words change in place, so whatever the ops leave alone keeps its timing and confidence.
+    // An edit made against an older version is rebased onto the edits since, as long as they
+    // changed other words; otherwise it's rejected with the block as it is now. Returns the edit
+    // as applied, against the version it was applied to.
+    pub fn edit_words(
+        &mut self,
+        user: &str,
+        edit: &WordsEdited,
+    ) -> Result<WordsEdited, EditConflict> {
+        let event_id = self.event_id;
+        let conflict = |current: Option<BlockMessage>| EditConflict {
+            event_id,
+            id: edit.id,
+            rejected: edit.ops.clone(),
+            current,
+        };
+        let block = match self.blocks.iter_mut().find(|block| block.id == edit.id) {
+            Some(block) if block.is_visible() => block,
+            _ => return Err(conflict(None)),
+        };
+
+        let before = block.clone();
+        let rebased = block
+            .ops_since(edit.base_version)
+            .and_then(|applied| transform_ops(&edit.ops, &applied));
+        let ops = match rebased {
+            Some(ops) if apply_ops(&mut block.words, &ops) => ops,
+            _ => return Err(conflict(Some(block.to_message(event_id)))),
+        };
+        // A block without words has nothing to play or split, deleting it is how it goes
+        if block.words.is_empty() {
+            block.words = before.words;
+            return Err(conflict(Some(block.to_message(event_id))));
+        }
+        let base_version = block.version;
+        block.corrected_text = Some(join_words(&block.words));
+        block.bump_version(Some(ops.clone()));
+        let after = block.clone();
+        let action = if ops.iter().all(|op| matches!(op, WordOp::SetSpeaker { .. })) {
+            AuditAction::SpeakerChange
+        } else {
+            AuditAction::Edit
+        };
+        self.audit(user, action, Some(&before), &after);
+        Ok(WordsEdited {
+            event_id,
+            id: edit.id,
+            base_version,
+            ops,
+        })
+    }
+
+    pub fn delete_block(&mut self, user: &str, id: BlockId) -> bool {
+        match self.blocks.iter_mut().find(|block| block.id == id) {
+            Some(block) if block.is_visible() => {
+                let before = block.clone();
+                block.is_deleted = true;
+                let after = block.clone();
+                self.record(
+                    user,
+                    AuditAction::Delete,
+                    vec![BlockChange { before, after }],
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Cuts a block in two before word `at_word_index`, the second half becoming a new block right
+    // below the first, optionally with a speaker of its own. Like a word edit, a split made against
+    // an old version of the block is a conflict: the words may have moved since.
+    pub fn split_block(
+        &mut self,
+        user: &str,
+        split: &SplitBlockMessage,
+    ) -> Result<BlockSplit, EditConflict> {
+        let event_id = self.event_id;
+        let new_id = self.next_block_id();
+        let idx = self.position(split.id);
+        let conflict = |current: Option<BlockMessage>| EditConflict {
+            event_id,
+            id: split.id,
+            rejected: Vec::new(),
+            current,
+        };
+        let idx = match idx {
+            Some(idx) if self.blocks[idx].is_visible() => idx,
+            _ => return Err(conflict(None)),
+        };
+        let block = &mut self.blocks[idx];
+        if block.version != split.version
+            || split.at_word_index == 0
+            || split.at_word_index >= block.words.len()
+        {
+            return Err(conflict(Some(block.to_message(event_id))));
+        }
+
+        let parent_before = block.clone();
+        let mut words = block.words.split_off(split.at_word_index);
+        let split_time = words[0].start;
+        let original_at = block
+            .original_words
+            .iter()
+            .position(|word| word.start >= split_time)
+            .unwrap_or(block.original_words.len());
+        let original_words = block.original_words.split_off(original_at);
+        let speaker = match &split.new_speaker {
+            Some(speaker) => {
+                for word in words.iter_mut() {
+                    word.speaker = Some(speaker.clone());
+                }
+                speaker.clone()
+            }
+            None => block.speaker.clone(),
+        };
+        let corrected_text = block.corrected_text.as_ref().map(|_| join_words(&words));
+        if block.corrected_text.is_some() {
+            block.corrected_text = Some(join_words(&block.words));
+        }
+        let removed = WordOp::DeleteWords {
+            start: split.at_word_index,
+            end: split.at_word_index + words.len(),
+        };
+        block.bump_version(Some(vec![removed]));
+
+        let new_block = StoredBlock {
+            id: new_id,
+            speaker,
+            words,
+            original_words,
+            corrected_text,
+            is_deleted: false,
+            merged_into: None,
+            source_file: block.source_file,
+            version: 0,
+            approved_version: None,
+            approved_by: None,
+            recent_edits: Vec::new(),
+        };
+        let new_block_message = new_block.to_message(event_id);
+        // Undone, the new half is simply a deleted block
+        let changes = vec![
+            BlockChange {
+                before: parent_before,
+                after: block.clone(),
+            },
+            BlockChange {
+                before: StoredBlock {
+                    is_deleted: true,
+                    ..new_block.clone()
+                },
+                after: new_block.clone(),
+            },
+        ];
+        self.blocks.insert(idx + 1, new_block);
+        self.record(user, AuditAction::Split, changes);
+
+        Ok(BlockSplit {
+            event_id,
+            id: split.id,
+            at_word_index: split.at_word_index,
+            new_block: new_block_message,
+            order: self.order(),
+        })
+    }
+
+    // Appends the words of block `id` to the first visible block above it, and hides `id`.
+    // Returns the block above as it stands after the merge. Refused (with the reason, for the
+    // user) if there's no block above, or the speakers differ and the merge doesn't say which one
+    // the merged block should have
+    pub fn merge_block_above(
+        &mut self,
+        user: &str,
+        merge: &MergeBlockMessage,
+    ) -> Result<BlockMerged, String> {
+        let idx = self
+            .position(merge.id)
+            .filter(|idx| self.blocks[*idx].is_visible())
+            .ok_or_else(|| format!("Block {} is already gone", merge.id))?;
+        let prev_idx = self.blocks[..idx]
+            .iter()
+            .rposition(|block| block.is_visible())
+            .ok_or_else(|| {
+                format!(
+                    "Block {} is the first block, there's nothing above it",
+                    merge.id
+                )
+            })?;
+        let speaker = match &merge.speaker {
+            Some(speaker) => speaker.clone(),
+            None if self.blocks[prev_idx].speaker == self.blocks[idx].speaker => {
+                self.blocks[idx].speaker.clone()
+            }
+            None => {
+                return Err(format!(
+                    "Block {} ({}) and the block above it ({}) have different speakers",
+                    merge.id, self.blocks[idx].speaker, self.blocks[prev_idx].speaker
+                ))
+            }
+        };
+
+        let (merged_before, above_before) =
+            (self.blocks[idx].clone(), self.blocks[prev_idx].clone());
+        let mut merged = self.blocks[idx].clone();
+        if merged.speaker != speaker {
+            set_speaker(&mut merged.words, &speaker);
+            set_speaker(&mut merged.original_words, &speaker);
+        }
+        let above_id = self.blocks[prev_idx].id;
+        let above = &mut self.blocks[prev_idx];
+        let mut ops = Vec::new();
+        if above.speaker != speaker {
+            ops.push(WordOp::SetSpeaker {
+                start: 0,
+                end: above.words.len(),
+                speaker: Some(speaker.clone()),
+            });
+            set_speaker(&mut above.words, &speaker);
+            set_speaker(&mut above.original_words, &speaker);
+            above.speaker = speaker;
+        }
+        if above.corrected_text.is_some() || merged.corrected_text.is_some() {
+            above.corrected_text = Some(format!("{} {}", above.text(), merged.text()));
+        }
+        // To anyone rebasing an edit of the block above, the merge is words added at its end
+        ops.push(WordOp::InsertWords {
+            at: above.words.len(),
+            texts: merged.words.iter().map(|w| w.text.clone()).collect(),
+        });
+        above.bump_version(Some(ops));
+        above.words.extend(merged.words);
+        above.original_words.extend(merged.original_words);
+        let into = above.to_message(self.event_id);
+        self.blocks[idx].merged_into = Some(above_id);
+        let changes = vec![
+            BlockChange {
+                before: merged_before,
+                after: self.blocks[idx].clone(),
+            },
+            BlockChange {
+                before: above_before,
+                after: self.blocks[prev_idx].clone(),
+            },
+        ];
+        self.record(user, AuditAction::Merge, changes);
+
+        Ok(BlockMerged {
+            event_id: self.event_id,
+            id: merge.id,
+            into,
+        })
+    }
+
+    // Takes back the user's latest operation. If any of its blocks has changed since (someone
+    // edited the words, say) it can't be, and is dropped with the reason why.
+    pub fn undo(&mut self, user: &str) -> Result<BlocksRestored, String> {
+        let history = self.histories.entry(user.to_string()).or_default();
+        let operation = history.undo.pop().ok_or("Nothing to undo")?;
+        let (restored, redo) = self.revert(user, AuditAction::Undo, operation)?;
+        let history = self.histories.entry(user.to_string()).or_default();
+        history.redo.push(redo);
+        Ok(restored)
+    }
+
+    pub fn redo(&mut self, user: &str) -> Result<BlocksRestored, String> {
+        let history = self.histories.entry(user.to_string()).or_default();
+        let operation = history.redo.pop().ok_or("Nothing to redo")?;
+        let (restored, undo) = self.revert(user, AuditAction::Redo, operation)?;
+        let history = self.histories.entry(user.to_string()).or_default();
+        history.undo.push(undo);
+        Ok(restored)
+    }
+
+    // Puts the operation's blocks back as they were before it, returning the operation that
+    // would put them back again. The blocks move up a version, they've changed for everyone else.
+    fn revert(
+        &mut self,
+        user: &str,
+        action: AuditAction,
+        operation: Operation,
+    ) -> Result<(BlocksRestored, Operation), String> {
+        for change in &operation.changes {
+            let current = self.position(change.after.id).map(|idx| &self.blocks[idx]);
+            match current {
+                Some(block)
+                    if block.version == change.after.version
+                        && block.is_visible() == change.after.is_visible() => {}
+                _ => {
+                    return Err(format!(
+                        "Block {} has changed since, that can no longer be undone",
+                        change.after.id
+                    ))
+                }
+            }
+        }
+
+        let mut inverse = Vec::with_capacity(operation.changes.len());
+        let (mut blocks, mut removed) = (Vec::new(), Vec::new());
+        for change in operation.changes {
+            let idx = self.position(change.after.id).unwrap();
+            let current = self.blocks[idx].clone();
+            let mut block = change.before;
+            block.version = current.version.max(block.version);
+            block.bump_version(None);
+            if block.is_visible() {
+                blocks.push(block.to_message(self.event_id));
+            } else {
+                removed.push(block.id);
+            }
+            self.blocks[idx] = block.clone();
+            self.audit(user, action, Some(&current), &block);
+            inverse.push(BlockChange {
+                before: current,
+                after: block,
+            });
+        }
+        let restored = BlocksRestored {
+            event_id: self.event_id,
+            blocks,
+            removed,
+            order: self.order(),
+        };
+        Ok((restored, Operation { changes: inverse }))
+    }
+
+    // Puts a block's speaker and text back as an audit entry saw them, as an operation of its own
+    // (so it can be undone in turn). Deleted and merged blocks have to be undone first.
+    pub fn restore_block(
+        &mut self,
+        user: &str,
+        id: BlockId,
+        snapshot: &BlockSnapshot,
+    ) -> Result<BlocksRestored, String> {
+        let block = match self.blocks.iter_mut().find(|block| block.id == id) {
+            Some(block) if block.is_visible() => block,
+            Some(_) => return Err(format!("Block {} was deleted or merged since", id)),
+            None => return Err(format!("There is no block {}", id)),
+        };
+        let before = block.clone();
+        if block.speaker != snapshot.speaker {
+            set_speaker(&mut block.words, &snapshot.speaker);
+            set_speaker(&mut block.original_words, &snapshot.speaker);
+            block.speaker = snapshot.speaker.clone();
+        }
+        block.corrected_text = Some(snapshot.text.clone());
+        block.realign();
+        block.bump_version(None);
+        let after = block.clone();
+        let message = after.to_message(self.event_id);
+        self.record(
+            user,
+            AuditAction::Restore,
+            vec![BlockChange { before, after }],
+        );
+        Ok(BlocksRestored {
+            event_id: self.event_id,
+            blocks: vec![message],
+            removed: Vec::new(),
+            order: self.order(),
+        })
+    }
+
+    // Gives the whole block to another speaker, as an operation of its own (so it can be undone)
+    pub fn reassign_speaker(
+        &mut self,
+        user: &str,
+        reassign: &ReassignBlockSpeaker,
+    ) -> Result<BlockMessage, String> {
+        let block = match self.blocks.iter_mut().find(|block| block.id == reassign.id) {
+            Some(block) if block.is_visible() => block,
+            Some(_) => return Err(format!("Block {} was deleted or merged", reassign.id)),
+            None => return Err(format!("There is no block {}", reassign.id)),
+        };
+        if block.speaker == reassign.speaker {
+            return Err(format!(
+                "Block {} is already speaker {}",
+                reassign.id, reassign.speaker
+            ));
+        }
+        let before = block.clone();
+        set_speaker(&mut block.words, &reassign.speaker);
+        set_speaker(&mut block.original_words, &reassign.speaker);
+        block.speaker = reassign.speaker.clone();
+        block.bump_version(None);
+        let after = block.clone();
+        let message = after.to_message(self.event_id);
+        self.record(
+            user,
+            AuditAction::SpeakerChange,
+            vec![BlockChange { before, after }],
+        );
+        Ok(message)
+    }
+
+    // Signs off on the block as the reviewer read it. Not an operation: there's nothing to undo,
+    // the next change to the block takes the approval away anyway.
+    pub fn approve_block(
+        &mut self,
+        user: &str,
+        approval: &BlockApproval,
+    ) -> Result<BlockApproved, String> {
+        let event_id = self.event_id;
+        let block = match self.blocks.iter_mut().find(|block| block.id == approval.id) {
+            Some(block) if block.is_visible() => block,
+            Some(_) => return Err(format!("Block {} was deleted or merged", approval.id)),
+            None => return Err(format!("There is no block {}", approval.id)),
+        };
+        if block.version != approval.version {
+            return Err(format!(
+                "Block {} changed since you read it, please review it again",
+                approval.id
+            ));
+        }
+        let before = block.clone();
+        block.approved_version = Some(block.version);
+        block.approved_by = Some(user.to_string());
+        let approved = BlockApproved {
+            event_id,
+            id: block.id,
+            version: block.version,
+            approved_by: user.to_string(),
+        };
+        let after = block.clone();
+        self.audit(user, AuditAction::Approve, Some(&before), &after);
+        Ok(approved)
+    }
+
+    // A new operation by `user`, which also means whatever they undid is gone for good
+    fn record(&mut self, user: &str, action: AuditAction, changes: Vec<BlockChange>) {
+        for change in &changes {
+            // The new half of a split didn't exist before it, whatever its undo copy says
+            let before = (action != AuditAction::Split || change.before.is_visible())
+                .then(|| &change.before);
+            self.audit(user, action, before, &change.after);
+        }
+        let history = self.histories.entry(user.to_string()).or_default();
+        history.undo.push(Operation { changes });
+        if history.undo.len() > MAX_UNDO {
+            history.undo.remove(0);
+        }
+        history.redo.clear();
+    }
+
+    fn audit(
+        &mut self,
+        user: &str,
+        action: AuditAction,
+        before: Option<&StoredBlock>,
+        after: &StoredBlock,
+    ) {
+        self.unlogged.push(AuditEntry {
+            seq: 0, // Numbered as it's written
+            at_ms: now_ms(),
+            user: user.to_string(),
+            event_id: self.event_id,
+            block_id: after.id,
+            action,
+            before: before.map(StoredBlock::snapshot),
+            after: after.snapshot(),
+        });
+    }
+
+    // Numbers a change for the event's subscribers. Done while the document is locked for the
+    // change itself, so the numbers follow the order the changes were made in.
+    pub fn update(&mut self, down_msg: DownMsg) -> DownMsg {
+        self.seq += 1;
+        DownMsg::EventUpdate(EventUpdate {
+            event_id: self.event_id,
+            seq: self.seq,
+            down_msg: Box::new(down_msg),
+        })
+    }
+
+    // What a client needs to see the event as it stands now, corrections and speakers included
+    pub fn snapshot(&self, manifest: &EventManifest) -> EventSnapshot {
+        let visible = self.blocks.iter().filter(|block| block.is_visible());
+        EventSnapshot {
+            event_id: self.event_id,
+            seq: self.seq,
+            blocks: visible
+                .clone()
+                .map(|block| block.to_message(self.event_id))
+                .collect(),
+            approvals: visible
+                .filter_map(|block| block.approval(self.event_id))
+                .collect(),
+            speakers: manifest.speakers.clone(),
+            audio_url: manifest.audio_url(),
+            is_finished: manifest.status == EventStatus::Finished,
+        }
+    }
+}
+
+// ------ ------
+//    States
+// ------ ------
+
+// A lock per event, loaded the first time it's asked for. The map's own lock is only held to find
+// one, so saving one event's document never holds up another event.
+static DOCUMENTS: Mutex<BTreeMap<EventId, Arc<Mutex<Option<EventDocument>>>>> =
+    Mutex::new(BTreeMap::new());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+fn document_path(event_id: EventId) -> PathBuf {
+    catalog::event_dir(event_id).join("document.json")
+}
+
+fn document_lock(event_id: EventId) -> Arc<Mutex<Option<EventDocument>>> {
+    DOCUMENTS
+        .lock()
+        .unwrap()
+        .entry(event_id)
+        .or_default()
+        .clone()
+}
+
+// Read-only access to an event document, loading it from disk the first time it is asked for
+pub fn with_document<R>(event_id: EventId, f: impl FnOnce(&EventDocument) -> R) -> R {
+    let lock = document_lock(event_id);
+    let mut document = lock.lock().unwrap();
+    f(document.get_or_insert_with(|| load_document(event_id)))
+}
+
+// A deleted event's document goes with it, once whoever is changing it has finished. Its id
+// may be given to a new event, which starts from nothing.
+pub fn forget_document(event_id: EventId) {
+    let lock = DOCUMENTS.lock().unwrap().remove(&event_id);
+    if let Some(lock) = lock {
+        lock.lock().unwrap().take();
+    }
+}
+
+// Mutate an event document and write it back to disk so a restart doesn't lose human corrections.
+// Every change subscribers hear about is numbered, so one that didn't number anything (rejected,
+// or a no-op) changed nothing and isn't written.
+pub fn update_document<R>(event_id: EventId, f: impl FnOnce(&mut EventDocument) -> R) -> R {
+    let lock = document_lock(event_id);
+    let mut document = lock.lock().unwrap();
+    let document = document.get_or_insert_with(|| load_document(event_id));
+    let seq = document.seq;
+    let result = f(document);
+    if document.seq != seq {
+        if let Err(err) = save_document(document) {
+            eprintln!("Failed to save document for event {}: {:?}", event_id, err);
+        }
+        audit::append(event_id, std::mem::take(&mut document.unlogged));
+    }
+    result
+}
+
+fn load_document(event_id: EventId) -> EventDocument {
+    match read_document_from_file(document_path(event_id)) {
+        Ok(mut document) => {
+            for block in document.blocks.iter_mut() {
+                // Documents from before splits only had blocks named after their files
+                if block.source_file.is_none() {
+                    block.source_file = Some(block.id);
+                }
+                // Documents from before we kept the original words never changed their words at all
+                if block.original_words.is_empty() {
+                    block.original_words = block.words.clone();
+                    block.realign();
+                }
+            }
+            document
+        }
+        Err(_) => EventDocument::new(event_id), // Nothing stored yet, the ingestion will fill it in
+    }
+}
+
+fn read_document_from_file(path: PathBuf) -> Result<EventDocument, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let document = serde_json::from_reader(reader)?;
+    Ok(document)
+}
+
+fn save_document(document: &EventDocument) -> Result<(), Box<dyn Error>> {
+    let path = document_path(document.event_id);
+    // Write to the side and rename, so a crash mid-write never leaves us with half a document
+    let tmp_path = path.with_extension("json.tmp");
+    serde_json::to_writer(File::create(&tmp_path)?, document)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+fn set_speaker(words: &mut [Word], speaker: &str) {
+    for word in words {
+        word.speaker = Some(speaker.to_string());
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+fn join_words(words: &[Word]) -> String {
+    words
+        .iter()
+        .map(|w| w.text.clone())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A word a second, each 800ms long, starting at second `first`
+    fn block(id: BlockId, first: usize, text: &str) -> BlockMessage {
+        BlockMessage {
+            event_id: 1,
+            id,
+            version: 0,
+            speaker: "A".to_string(),
+            words: text
+                .split_whitespace()
+                .enumerate()
+                .map(|(idx, text)| Word {
+                    confidence: 1.0,
+                    start: (first + idx) * 1000,
+                    end: (first + idx) * 1000 + 800,
+                    speaker: Some("A".to_string()),
+                    text: text.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn document() -> EventDocument {
+        let mut document = EventDocument::new(1);
+        document.insert_block(1, &block(1, 0, "one two three four"));
+        document.insert_block(2, &block(2, 4, "five six"));
+        document
+    }
+
+    fn split(id: BlockId, version: u64, at_word_index: usize) -> SplitBlockMessage {
+        SplitBlockMessage {
+            event_id: 1,
+            id,
+            version,
+            at_word_index,
+            new_speaker: None,
+        }
+    }
+
+    fn texts(document: &EventDocument) -> Vec<(BlockId, String)> {
+        document
+            .blocks
+            .iter()
+            .filter(|block| block.is_visible())
+            .map(|block| (block.id, block.text()))
+            .collect()
+    }
+
+    #[test]
+    fn a_split_at_either_end_of_a_block_is_refused() {
+        let mut document = document();
+        for at_word_index in [0, 4, 5] {
+            let conflict = document
+                .split_block("ann", &split(1, 0, at_word_index))
+                .unwrap_err();
+            assert_eq!(conflict.current.unwrap().version, 0);
+        }
+        assert_eq!(document.order(), vec![1, 2]);
+        assert!(document.undo("ann").is_err());
+    }
+
+    #[test]
+    fn a_split_of_a_stale_version_is_refused() {
+        let mut document = document();
+        assert!(document.split_block("ann", &split(1, 1, 2)).is_err());
+        assert!(document.split_block("ann", &split(7, 0, 2)).is_err());
+        assert_eq!(document.order(), vec![1, 2]);
+    }
+
+    #[test]
+    fn a_split_moves_the_rest_of_the_block_below_it() {
+        let mut document = document();
+        let split = document.split_block("ann", &split(1, 0, 3)).unwrap();
+        assert_eq!(split.new_block.id, 3);
+        assert_eq!(split.order, vec![1, 3, 2]);
+        assert_eq!(
+            texts(&document),
+            vec![
+                (1, "one two three".to_string()),
+                (3, "four".to_string()),
+                (2, "five six".to_string()),
+            ]
+        );
+        assert_eq!(document.blocks[0].version, 1);
+        assert_eq!(document.blocks[1].version, 0);
+        assert_eq!(document.blocks[1].source_file, Some(1));
+        assert!(!document.is_in_id_order());
+    }
+
+    #[test]
+    fn a_split_can_give_the_second_half_a_new_speaker() {
+        let mut document = document();
+        let mut message = split(1, 0, 2);
+        message.new_speaker = Some("B".to_string());
+        let split = document.split_block("ann", &message).unwrap();
+        assert_eq!(split.new_block.speaker, "B");
+        assert!(split
+            .new_block
+            .words
+            .iter()
+            .all(|word| word.speaker.as_deref() == Some("B")));
+        assert_eq!(document.blocks[0].speaker, "A");
+    }
+
+    #[test]
+    fn a_corrected_block_splits_its_original_words_by_time() {
+        let mut document = document();
+        let edit = WordsEdited {
+            event_id: 1,
+            id: 1,
+            base_version: 0,
+            ops: vec![WordOp::ReplaceWords {
+                start: 1,
+                end: 2,
+                texts: vec!["TWO".to_string(), "and".to_string()],
+            }],
+        };
+        document.edit_words("ann", &edit).unwrap();
+        document.split_block("ann", &split(1, 1, 3)).unwrap();
+        let (first, second) = (&document.blocks[0], &document.blocks[1]);
+        assert_eq!(first.corrected_text.as_deref(), Some("one TWO and"));
+        assert_eq!(second.corrected_text.as_deref(), Some("three four"));
+        assert_eq!(join_words(&first.original_words), "one two");
+        assert_eq!(join_words(&second.original_words), "three four");
+    }
+
+    #[test]
+    fn an_edit_that_leaves_no_words_is_refused() {
+        let mut document = document();
+        let edit = WordsEdited {
+            event_id: 1,
+            id: 2,
+            base_version: 0,
+            ops: vec![WordOp::DeleteWords { start: 0, end: 2 }],
+        };
+        let conflict = document.edit_words("ann", &edit).unwrap_err();
+        assert_eq!(conflict.current.unwrap().words.len(), 2);
+        assert_eq!(document.blocks[1].text(), "five six");
+        assert_eq!(document.blocks[1].version, 0);
+    }
+
+    #[test]
+    fn undoing_a_split_puts_the_block_back_together() {
+        let mut document = document();
+        document.split_block("ann", &split(1, 0, 2)).unwrap();
+        let restored = document.undo("ann").unwrap();
+        assert_eq!(restored.removed, vec![3]);
+        assert_eq!(restored.order, vec![1, 2]);
+        assert_eq!(restored.blocks[0].version, 2);
+        assert_eq!(
+            texts(&document),
+            vec![
+                (1, "one two three four".to_string()),
+                (2, "five six".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn block_files_after_a_split_still_go_in_file_order() {
+        let mut document = EventDocument::new(1);
+        document.insert_block(1, &block(1, 0, "one two three four"));
+        let split = document.split_block("ann", &split(1, 0, 2)).unwrap();
+        assert_eq!(split.new_block.id, 2);
+        // File 2's id is taken by the split, and file 1 has been read already
+        let inserted = document.insert_block(2, &block(2, 4, "five six")).unwrap();
+        assert_eq!(inserted.id, 3);
+        assert!(document.insert_block(1, &block(1, 0, "one two")).is_none());
+        assert_eq!(document.order(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn a_split_leaves_the_id_reserved_for_a_partial_block_alone() {
+        let mut document = EventDocument::new(1);
+        document.insert_block(1, &block(1, 0, "one two three four"));
+        assert_eq!(document.reserve_block_id(2), 2);
+        let split = document.split_block("ann", &split(1, 0, 2)).unwrap();
+        assert_eq!(split.new_block.id, 3);
+        // However often the partial changes before its block is final
+        assert_eq!(document.reserve_block_id(2), 2);
+        let inserted = document.insert_block(2, &block(2, 4, "five six")).unwrap();
+        assert_eq!(inserted.id, 2);
+        assert!(document.reserved_ids.is_empty());
+        assert_eq!(document.order(), vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn a_partial_block_whose_file_number_is_taken_gets_the_next_free_id() {
+        let mut document = EventDocument::new(1);
+        document.insert_block(1, &block(1, 0, "one two three four"));
+        document.split_block("ann", &split(1, 0, 2)).unwrap();
+        assert_eq!(document.reserve_block_id(2), 3);
+        assert_eq!(document.reserve_block_id(3), 4);
+        let inserted = document.insert_block(3, &block(3, 6, "seven")).unwrap();
+        assert_eq!(inserted.id, 4);
+        let inserted = document.insert_block(2, &block(2, 4, "five six")).unwrap();
+        assert_eq!(inserted.id, 3);
+    }
+}
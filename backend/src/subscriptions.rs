@@ -0,0 +1,88 @@
+use moon::*;
+use shared::{DownMsg, EventId};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// A session that drops out (a network blip, a laptop waking up) comes back under the same id.
+// It stays subscribed for this long meanwhile, and catches up on what it missed with a resync.
+const RECONNECT_GRACE: Duration = Duration::from_secs(120);
+
+// ------ ------
+//     Types
+// ------ ------
+
+struct Subscriber {
+    session_id: SessionId,
+    gone_since: Option<Instant>, // When we last noticed the session wasn't connected
+}
+
+// ------ ------
+//    States
+// ------ ------
+
+// Which sessions are looking at which event. A session looks at one event at a time.
+static SUBSCRIBERS: Mutex<BTreeMap<EventId, Vec<Subscriber>>> = Mutex::new(BTreeMap::new());
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Choosing another event drops the session's previous subscription
+pub fn subscribe(event_id: EventId, session_id: SessionId) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    for event_subscribers in subscribers.values_mut() {
+        event_subscribers.retain(|subscriber| subscriber.session_id != session_id);
+    }
+    subscribers.entry(event_id).or_default().push(Subscriber {
+        session_id,
+        gone_since: None,
+    });
+}
+
+// The sessions subscribed to `event_id`, the briefly disconnected ones included. Only a session
+// gone for longer than RECONNECT_GRACE is forgotten.
+pub fn subscribers(event_id: EventId) -> Vec<SessionId> {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    let event_subscribers = match subscribers.get_mut(&event_id) {
+        Some(event_subscribers) => event_subscribers,
+        None => return Vec::new(),
+    };
+    for subscriber in event_subscribers.iter_mut() {
+        if sessions::by_session_id()
+            .get(subscriber.session_id)
+            .is_some()
+        {
+            subscriber.gone_since = None;
+        } else if subscriber.gone_since.is_none() {
+            subscriber.gone_since = Some(Instant::now());
+        }
+    }
+    event_subscribers.retain(|subscriber| {
+        subscriber
+            .gone_since
+            .map_or(true, |gone_since| gone_since.elapsed() < RECONNECT_GRACE)
+    });
+    let session_ids: Vec<SessionId> = event_subscribers
+        .iter()
+        .map(|subscriber| subscriber.session_id)
+        .collect();
+    if session_ids.is_empty() {
+        subscribers.remove(&event_id);
+    }
+    session_ids
+}
+
+// The event has been deleted, there's nothing left to look at
+pub fn drop_event(event_id: EventId) {
+    SUBSCRIBERS.lock().unwrap().remove(&event_id);
+}
+
+// Only the sessions looking at the event hear about its blocks, and only the connected ones can
+pub async fn broadcast(event_id: EventId, down_msg: &DownMsg, cor_id: CorId) {
+    for session_id in subscribers(event_id) {
+        if let Some(session) = sessions::by_session_id().get(session_id) {
+            session.send_down_msg(down_msg, cor_id).await;
+        }
+    }
+}
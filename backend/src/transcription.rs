@@ -0,0 +1,506 @@
+use crate::assembly_ai::AssemblyAi;
+use crate::catalog;
+use moon::tokio::time::{sleep, Duration};
+use moon::*;
+use shared::split::{split_transcript, write_block_files, SplitOptions};
+use shared::transcription::{self, OfflineProvider, TranscriptionProvider};
+use shared::{EventId, EventStatus, Utterance};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+const PACING: Pacing = Pacing {
+    max_attempts: 5,
+    retry_base_delay: Duration::from_secs(2),
+    poll_interval: Duration::from_secs(5),
+};
+
+// ------ ------
+//     Types
+// ------ ------
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub enum JobState {
+    Queued,
+    Processing,
+    Completed,
+    Error(String),
+}
+
+// One transcription per event, saved after every step so a restart carries on where it stopped
+// rather than paying to upload and transcribe the same audio twice
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct TranscriptionJob {
+    pub event_id: EventId,
+    pub state: JobState,
+    pub upload_url: Option<String>,
+    pub transcript_id: Option<String>,
+    pub attempts: u32,
+}
+
+impl TranscriptionJob {
+    fn new(event_id: EventId) -> Self {
+        Self {
+            event_id,
+            state: JobState::Queued,
+            upload_url: None,
+            transcript_id: None,
+            attempts: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(self.state, JobState::Completed | JobState::Error(_))
+    }
+}
+
+// How long a job waits on the service, and how often it lets it fail before giving up
+struct Pacing {
+    max_attempts: u32,
+    retry_base_delay: Duration,
+    poll_interval: Duration,
+}
+
+// What a single step of the job left us with
+enum Progress {
+    Advanced,
+    Waiting,
+    Done,
+}
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Hand an event's audio over to be transcribed, its blocks land in the event directory when done
+pub fn enqueue(event_id: EventId) {
+    let job = TranscriptionJob::new(event_id);
+    if let Err(err) = save_job(&job) {
+        eprintln!("Failed to save job for event {}: {:?}", event_id, err);
+    }
+    tokio::spawn(run_job(job));
+}
+
+// Pick up the jobs a previous run of the backend left unfinished
+pub fn resume_jobs() {
+    for manifest in catalog::list_events() {
+        if let Ok(job) = load_job(manifest.id) {
+            if !job.is_done() {
+                println!("Resuming transcription of event {}", job.event_id);
+                tokio::spawn(run_job(job));
+            }
+        }
+    }
+}
+
+async fn run_job(mut job: TranscriptionJob) {
+    if let Some(provider) = offline_provider(job.event_id) {
+        return run_offline_job(job, provider);
+    }
+    let client = match AssemblyAi::from_env() {
+        Ok(client) => client,
+        Err(err) => return fail_job(&mut job, format!("No AssemblyAI client: {}", err)),
+    };
+    run_online_job(&client, job, &PACING).await
+}
+
+async fn run_online_job(client: &AssemblyAi, mut job: TranscriptionJob, pacing: &Pacing) {
+    loop {
+        if !catalog::event_exists(job.event_id) {
+            println!(
+                "Event {} was deleted, dropping its transcription",
+                job.event_id
+            );
+            return;
+        }
+        // Only the message survives, the boxed error can't be held across the sleeps below
+        let progress = step(client, &mut job).await.map_err(|err| err.to_string());
+        match progress {
+            Ok(Progress::Advanced) => job.attempts = 0,
+            Ok(Progress::Waiting) => {
+                job.attempts = 0;
+                sleep(pacing.poll_interval).await;
+            }
+            Ok(Progress::Done) => {
+                if let Err(err) = save_job(&job) {
+                    eprintln!("Failed to save job for event {}: {:?}", job.event_id, err);
+                }
+                return;
+            }
+            Err(err) => {
+                job.attempts += 1;
+                if job.attempts >= pacing.max_attempts {
+                    return fail_job(&mut job, err);
+                }
+                // 2s, 4s, 8s, ... the service is either busy or down, neither is fixed by
+                // hammering it
+                let delay = pacing.retry_base_delay * 2u32.pow(job.attempts - 1);
+                eprintln!(
+                    "Transcription of event {} failed (attempt {}), retrying in {:?}: {}",
+                    job.event_id, job.attempts, delay, err
+                );
+                sleep(delay).await;
+            }
+        }
+        if let Err(err) = save_job(&job) {
+            eprintln!("Failed to save job for event {}: {:?}", job.event_id, err);
+        }
+    }
+}
+
+// Upload -> submit -> poll, each step only once it has what it needs from the one before
+async fn step(client: &AssemblyAi, job: &mut TranscriptionJob) -> Result<Progress, Box<dyn Error>> {
+    let upload_url = match &job.upload_url {
+        Some(upload_url) => upload_url.clone(),
+        None => {
+            let manifest = catalog::load_manifest(job.event_id);
+            let recording = fs::read(catalog::event_dir(job.event_id).join(manifest.audio_file))?;
+            let upload_url = client.upload(recording).await?;
+            println!("Event {} uploaded to {:?}", job.event_id, upload_url);
+            job.upload_url = Some(upload_url);
+            return Ok(Progress::Advanced);
+        }
+    };
+
+    let transcript_id = match &job.transcript_id {
+        Some(transcript_id) => transcript_id.clone(),
+        None => {
+            let transcript_id = client.submit(&upload_url).await?;
+            println!(
+                "Event {} transcript requested: {}",
+                job.event_id, transcript_id
+            );
+            job.transcript_id = Some(transcript_id);
+            job.state = JobState::Processing;
+            catalog::set_status(job.event_id, EventStatus::Transcribing);
+            return Ok(Progress::Advanced);
+        }
+    };
+
+    let (transcript, raw) = client.poll(&transcript_id).await?;
+    match transcript.status.as_str() {
+        "completed" => {
+            let blocks = transcription::AssemblyAi.normalize(&raw)?;
+            let count = write_blocks(&catalog::event_dir(job.event_id), blocks)?;
+            println!("Event {} transcribed into {} blocks", job.event_id, count);
+            job.state = JobState::Completed;
+            catalog::set_status(job.event_id, EventStatus::Finished);
+            Ok(Progress::Done)
+        }
+        "error" => {
+            // The service gave up on the audio itself, retrying won't change its mind
+            let error = transcript.error.unwrap_or_else(|| "unknown error".into());
+            fail_job(job, error);
+            Ok(Progress::Done)
+        }
+        status => {
+            println!("... event {} status: {}", job.event_id, status);
+            Ok(Progress::Waiting)
+        }
+    }
+}
+
+// With JADILI_OFFLINE_TRANSCRIPTS set, jobs read `<dir>/event_NNNN.json` instead of calling out,
+// in the format JADILI_OFFLINE_FORMAT names (assembly_ai unless told otherwise)
+fn offline_provider(event_id: EventId) -> Option<OfflineProvider> {
+    let dir = PathBuf::from(env::var("JADILI_OFFLINE_TRANSCRIPTS").ok()?);
+    let format_name = env::var("JADILI_OFFLINE_FORMAT").unwrap_or_else(|_| "assembly_ai".into());
+    match transcription::provider_by_name(&format_name) {
+        Some(format) => Some(OfflineProvider::new(
+            dir.join(format!("event_{:04}.json", event_id)),
+            format,
+        )),
+        None => {
+            eprintln!(
+                "Unknown transcript format {:?}, not transcribing offline",
+                format_name
+            );
+            None
+        }
+    }
+}
+
+fn run_offline_job(mut job: TranscriptionJob, provider: OfflineProvider) {
+    let written = provider
+        .transcribe()
+        .map_err(|err| err.to_string())
+        .and_then(|blocks| {
+            write_blocks(&catalog::event_dir(job.event_id), blocks).map_err(|err| err.to_string())
+        });
+    match written {
+        Ok(count) => {
+            println!("Event {} read into {} blocks", job.event_id, count);
+            job.state = JobState::Completed;
+            catalog::set_status(job.event_id, EventStatus::Finished);
+            if let Err(err) = save_job(&job) {
+                eprintln!("Failed to save job for event {}: {:?}", job.event_id, err);
+            }
+        }
+        Err(err) => fail_job(&mut job, err),
+    }
+}
+
+fn fail_job(job: &mut TranscriptionJob, error: String) {
+    eprintln!("Transcription of event {} failed: {}", job.event_id, error);
+    job.state = JobState::Error(error);
+    catalog::set_status(job.event_id, EventStatus::Error);
+    if let Err(err) = save_job(job) {
+        eprintln!("Failed to save job for event {}: {:?}", job.event_id, err);
+    }
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// Splits the transcript into `block_NNNN.json` files for the ingestion to pick up, then drops
+// the finished marker
+fn write_blocks(event_dir: &Path, utterances: Vec<Utterance>) -> Result<usize, Box<dyn Error>> {
+    let blocks = split_transcript(utterances, &SplitOptions::default());
+    let count = write_block_files(event_dir, &blocks, 1)?;
+    File::create(event_dir.join("__event_finished"))?;
+    Ok(count)
+}
+
+fn job_path(event_id: EventId) -> PathBuf {
+    catalog::event_dir(event_id).join("job.json")
+}
+
+fn load_job(event_id: EventId) -> Result<TranscriptionJob, Box<dyn Error>> {
+    let file = File::open(job_path(event_id))?;
+    let reader = BufReader::new(file);
+    let job = serde_json::from_reader(reader)?;
+    Ok(job)
+}
+
+fn save_job(job: &TranscriptionJob) -> Result<(), Box<dyn Error>> {
+    let file = File::create(job_path(job.event_id))?;
+    serde_json::to_writer_pretty(file, job)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moon::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use moon::tokio::net::{TcpListener, TcpStream};
+    use std::future::Future;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    const FAST: Pacing = Pacing {
+        max_attempts: 3,
+        retry_base_delay: Duration::from_millis(10),
+        poll_interval: Duration::from_millis(10),
+    };
+
+    const UPLOADED: &str = r#"{"upload_url": "https://cdn.example/audio-1"}"#;
+    const QUEUED: &str = r#"{"id": "tx-1", "status": "queued"}"#;
+    const COMPLETED: &str = r#"{
+        "id": "tx-1",
+        "status": "completed",
+        "utterances": [{
+            "confidence": 0.9, "start": 0, "end": 900, "speaker": "A", "text": "Good evening",
+            "words": [
+                {"confidence": 0.9, "start": 0, "end": 400, "speaker": "A", "text": "Good"},
+                {"confidence": 0.9, "start": 500, "end": 900, "speaker": "A", "text": "evening"}
+            ]
+        }]
+    }"#;
+
+    // Stands in for AssemblyAI, answering each request with the next of its responses. Once
+    // they run out it stops listening, so anything further is refused.
+    struct MockService {
+        url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockService {
+        async fn start(responses: Vec<(u16, &'static str)>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let received = requests.clone();
+            tokio::spawn(async move {
+                for (status, body) in responses {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let request = read_request(&mut stream).await;
+                    received.lock().unwrap().push(request);
+                    let response = format!(
+                        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            });
+            Self { url, requests }
+        }
+
+        fn client(&self) -> AssemblyAi {
+            AssemblyAi::new(&self.url, "test-key").unwrap()
+        }
+
+        // Whole requests, headers and body
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        // Just "POST /upload" and the like
+        fn request_lines(&self) -> Vec<String> {
+            self.requests()
+                .iter()
+                .filter_map(|request| Some(request.lines().next()?.rsplit_once(' ')?.0.to_string()))
+                .collect()
+        }
+    }
+
+    // The headers, then as much body as they announce
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+            let head_end = match request.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+                Some(position) => position + 4,
+                None => continue,
+            };
+            let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .and_then(|length| length.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= head_end + length {
+                break;
+            }
+        }
+        String::from_utf8_lossy(&request).into_owned()
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // A freshly uploaded event, its transcription not started yet
+    fn uploaded_event(event_id: EventId) -> TranscriptionJob {
+        let event_dir = catalog::event_dir(event_id);
+        let _ = fs::remove_dir_all(&event_dir);
+        fs::create_dir_all(&event_dir).unwrap();
+        let audio_file = catalog::load_manifest(event_id).audio_file;
+        fs::write(event_dir.join(audio_file), b"RIFF not really a recording").unwrap();
+        TranscriptionJob::new(event_id)
+    }
+
+    #[test]
+    fn a_job_uploads_submits_and_polls_until_the_transcript_is_completed() {
+        block_on(async {
+            let service = MockService::start(vec![
+                (200, UPLOADED),
+                (200, QUEUED),
+                (200, r#"{"id": "tx-1", "status": "processing"}"#),
+                (200, COMPLETED),
+            ])
+            .await;
+            // The way a developer points the backend at a mock, the other tests skip the env
+            env::set_var("JADILI_AAI_BASE_URL", &service.url);
+            env::set_var("JADILI_AAI_KEY", "test-key");
+            let client = AssemblyAi::from_env().unwrap();
+            run_online_job(&client, uploaded_event(61), &FAST).await;
+
+            assert_eq!(
+                service.request_lines(),
+                vec![
+                    "POST /upload",
+                    "POST /transcript",
+                    "GET /transcript/tx-1",
+                    "GET /transcript/tx-1",
+                ]
+            );
+            let submit = &service.requests()[1];
+            assert!(submit.to_lowercase().contains("authorization: test-key"));
+            assert!(submit.contains(r#""audio_url":"https://cdn.example/audio-1""#));
+            assert!(submit.contains(r#""speaker_labels":true"#));
+
+            assert_eq!(load_job(61).unwrap().state, JobState::Completed);
+            let event_dir = catalog::event_dir(61);
+            assert!(event_dir.join("block_0001.json").exists());
+            assert!(event_dir.join("__event_finished").exists());
+            assert_eq!(catalog::load_manifest(61).status, EventStatus::Finished);
+        });
+    }
+
+    #[test]
+    fn a_transcript_the_service_gave_up_on_fails_the_job_without_retrying() {
+        block_on(async {
+            let service = MockService::start(vec![
+                (200, UPLOADED),
+                (200, QUEUED),
+                (
+                    200,
+                    r#"{"id": "tx-1", "status": "error", "error": "Audio is too short"}"#,
+                ),
+            ])
+            .await;
+            run_online_job(&service.client(), uploaded_event(62), &FAST).await;
+
+            assert_eq!(service.requests().len(), 3);
+            let job = load_job(62).unwrap();
+            assert_eq!(job.state, JobState::Error("Audio is too short".to_string()));
+            assert!(!catalog::event_dir(62).join("block_0001.json").exists());
+            assert_eq!(catalog::load_manifest(62).status, EventStatus::Error);
+        });
+    }
+
+    #[test]
+    fn a_failed_request_is_retried_after_a_growing_delay() {
+        block_on(async {
+            let service = MockService::start(vec![
+                (503, "{}"),
+                (500, "{}"),
+                (200, UPLOADED),
+                (200, QUEUED),
+                (200, COMPLETED),
+            ])
+            .await;
+            let started = Instant::now();
+            run_online_job(&service.client(), uploaded_event(63), &FAST).await;
+
+            // 10ms after the first failure, 20ms after the second
+            assert!(started.elapsed() >= Duration::from_millis(30));
+            assert_eq!(service.request_lines()[..3], ["POST /upload"; 3]);
+            let job = load_job(63).unwrap();
+            assert_eq!(job.state, JobState::Completed);
+            assert_eq!(job.attempts, 0);
+        });
+    }
+
+    #[test]
+    fn a_job_gives_up_once_it_has_failed_too_often() {
+        block_on(async {
+            let service = MockService::start(vec![(500, "{}"); 3]).await;
+            run_online_job(&service.client(), uploaded_event(64), &FAST).await;
+
+            assert_eq!(service.requests().len(), 3);
+            let job = load_job(64).unwrap();
+            assert!(matches!(&job.state, JobState::Error(error) if error.contains("500")));
+            assert_eq!(job.attempts, 3);
+            assert_eq!(job.upload_url, None);
+            assert_eq!(catalog::load_manifest(64).status, EventStatus::Error);
+        });
+    }
+}
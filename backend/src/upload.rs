@@ -0,0 +1,164 @@
+use crate::{auth, catalog, transcription};
+use moon::actix_web::http::header;
+use moon::actix_web::{web, HttpRequest, HttpResponse};
+use moon::futures::StreamExt;
+use moon::*;
+use shared::roles::Role;
+use shared::{DownMsg, EventId};
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+
+// Recordings of a long meeting run to a few hundred MB, anything past this is a mistake
+const DEFAULT_MAX_UPLOAD_MB: u64 = 2048;
+// Chunks off the wire are small, they're gathered into writes of about this much
+const WRITE_SIZE: usize = 1024 * 1024;
+
+// ------ ------
+//     Types
+// ------ ------
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+pub struct UploadQuery {
+    title: String,
+    filename: String,
+    date: Option<String>,
+    language: Option<String>,
+}
+
+// ------ ------
+//   Handlers
+// ------ ------
+
+// POST /upload_event?title=..&filename=.. with the raw audio as the body. Streams the audio into
+// a freshly allocated event directory, so we never hold a whole recording in memory, and refuses
+// a body over JADILI_MAX_UPLOAD_MB. Only admins create events, their login token comes as
+// `Authorization: Bearer <token>`.
+pub async fn upload_event(
+    request: HttpRequest,
+    query: web::Query<UploadQuery>,
+    mut payload: web::Payload,
+) -> HttpResponse {
+    let user = match auth::authenticate(auth::bearer_token(&request)) {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    if auth::role(&user, None) < Role::Admin {
+        println!("Refusing an upload from {}, who isn't an admin", user);
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let max_bytes = max_upload_bytes();
+    let announced = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if announced.map_or(false, |length| length > max_bytes) {
+        return HttpResponse::PayloadTooLarge().finish();
+    }
+
+    let query = query.into_inner();
+    println!("Upload event {:?}", query);
+
+    let manifest = match catalog::create_event(
+        query.title,
+        query.date.unwrap_or_default(),
+        query.language,
+        audio_extension(&query.filename),
+    ) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("Failed to create event: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let audio_path = catalog::event_dir(manifest.id).join(&manifest.audio_file);
+    let mut file = match File::create(&audio_path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to create {:?}: {:?}", audio_path, err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let mut received: u64 = 0;
+    let mut pending: Vec<u8> = Vec::with_capacity(WRITE_SIZE);
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                discard_upload(manifest.id, &err.to_string());
+                return HttpResponse::BadRequest().finish();
+            }
+        };
+        received += chunk.len() as u64;
+        if received > max_bytes {
+            discard_upload(manifest.id, &format!("it's over {} bytes", max_bytes));
+            return HttpResponse::PayloadTooLarge().finish();
+        }
+        pending.extend_from_slice(&chunk);
+        if pending.len() >= WRITE_SIZE {
+            file = match write_off_thread(file, std::mem::take(&mut pending)).await {
+                Ok(file) => file,
+                Err(err) => {
+                    discard_upload(manifest.id, &err);
+                    return HttpResponse::InternalServerError().finish();
+                }
+            };
+        }
+    }
+    if let Err(err) = write_off_thread(file, pending).await {
+        discard_upload(manifest.id, &err);
+        return HttpResponse::InternalServerError().finish();
+    }
+    println!("Stored audio for event {} in {:?}", manifest.id, audio_path);
+
+    transcription::enqueue(manifest.id);
+
+    let events = catalog::list_events()
+        .iter()
+        .map(|manifest| manifest.to_summary())
+        .collect();
+    sessions::broadcast_down_msg(&DownMsg::EventList(events), CorId::new()).await;
+
+    HttpResponse::Ok().json(manifest.to_summary())
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// Disk writes block, so they go to actix's blocking threads rather than hold up this one
+async fn write_off_thread(mut file: File, data: Vec<u8>) -> Result<File, String> {
+    web::block(move || file.write_all(&data).map(|()| file))
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| err.to_string())
+}
+
+// Half an upload is no event at all, don't leave it lying around in the catalog
+fn discard_upload(event_id: EventId, reason: &str) {
+    eprintln!("Upload for event {} failed: {}", event_id, reason);
+    let _ = fs::remove_dir_all(catalog::event_dir(event_id));
+}
+
+fn max_upload_bytes() -> u64 {
+    env::var("JADILI_MAX_UPLOAD_MB")
+        .ok()
+        .and_then(|mb| mb.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MAX_UPLOAD_MB)
+        * 1024
+        * 1024
+}
+
+// Keep whatever extension the browser told us about, as long as it's something sane for a file name
+fn audio_extension(filename: &str) -> String {
+    match filename.rsplit_once('.') {
+        Some((_, ext)) if !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            ext.to_ascii_lowercase()
+        }
+        _ => "wav".to_string(),
+    }
+}
@@ -0,0 +1,31 @@
+// Functions called by Rust, for now
+
+// fetch rather than a plain link, a link can't carry the login token. Saves the file under the
+// name the backend gives it; `onDone` gets the HTTP status, or 0 when the request never got one.
+export function downloadExport(url, token, onDone) {
+    fetch(url, { headers: { "Authorization": "Bearer " + token } })
+        .then(function(response) {
+            if (!response.ok) {
+                onDone(response.status);
+                return;
+            }
+            const disposition = response.headers.get("Content-Disposition") || "";
+            const filename = /filename="([^"]+)"/.exec(disposition);
+            return response.blob().then(function(blob) {
+                const link = document.createElement("a");
+                link.href = URL.createObjectURL(blob);
+                link.download = filename ? filename[1] : "";
+                document.body.appendChild(link);
+                link.click();
+                link.remove();
+                // Give the browser a moment to start saving before letting go of the blob
+                setTimeout(function() {
+                    URL.revokeObjectURL(link.href);
+                }, 1000);
+                onDone(response.status);
+            });
+        })
+        .catch(function() {
+            onDone(0);
+        });
+}
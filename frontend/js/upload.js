@@ -0,0 +1,29 @@
+// Functions called by Rust, for now
+
+// XHR rather than fetch, fetch can't tell us how far along an upload is. `onDone` gets the HTTP
+// status, or 0 when the request never got one.
+export function uploadEventAudio(inputId, title, token, onProgress, onDone) {
+    const input = document.getElementById(inputId);
+    if (!input || input.files.length === 0) {
+        onDone(0);
+        return;
+    }
+    const file = input.files[0];
+    const params = new URLSearchParams({ title: title, filename: file.name });
+
+    const request = new XMLHttpRequest();
+    request.open("POST", "/upload_event?" + params.toString());
+    request.setRequestHeader("Authorization", "Bearer " + token);
+    request.upload.onprogress = function(event) {
+        if (event.lengthComputable) {
+            onProgress(event.loaded / event.total * 100.0);
+        }
+    };
+    request.onload = function() {
+        onDone(request.status);
+    };
+    request.onerror = function() {
+        onDone(0);
+    };
+    request.send(file);
+}
@@ -4,8 +4,11 @@ use crate::{
     login_page,
     router::{previous_route, router, Route},
 };
-use shared::{BlockId, EventId, Word};
-use zoon::*;
+use shared::roles::Role;
+use shared::{BlockApproved, BlockId, EventId, LoginAccepted, UpMsg, Word};
+use zoon::{eprintln, *};
+
+const LOGIN_STORAGE_KEY: &str = "jadili-login";
 
 // ------ ------
 //     Types
@@ -28,10 +31,12 @@ pub enum PageId {
 #[derive(Debug)]
 pub struct RenderBlock {
     pub id: BlockId,
-    pub speaker: String,
+    pub speaker: Mutable<String>,
     pub raw_words: MutableVec<Word>,
     pub full_text: Mutable<String>,
     pub is_visible: Mutable<bool>,
+    pub version: Mutable<u64>, // The backend's version of the block we last heard about
+    pub approval: Mutable<Option<BlockApproved>>, // Only holds while its version is the block's
 }
 
 // ------ ------
@@ -43,6 +48,18 @@ pub fn logged_user() -> &'static Mutable<Option<String>> {
     Mutable::new(None)
 }
 
+// The backend's token for our login, sent with every UpMsg
+#[static_ref]
+fn auth_token() -> &'static Mutable<Option<String>> {
+    Mutable::new(None)
+}
+
+// Outside any event, each event tells us our role in it when we choose it
+#[static_ref]
+pub fn user_role() -> &'static Mutable<Option<Role>> {
+    Mutable::new(None)
+}
+
 #[static_ref]
 fn page_id() -> &'static Mutable<PageId> {
     Mutable::new(PageId::Unknown)
@@ -56,6 +73,19 @@ pub fn is_user_logged() -> bool {
     logged_user().map(Option::is_some)
 }
 
+pub fn connection_auth_token() -> Option<AuthToken> {
+    auth_token().get_cloned().map(AuthToken::new)
+}
+
+// For the requests that don't go over the connection (uploads)
+pub fn login_token() -> Option<String> {
+    auth_token().get_cloned()
+}
+
+pub fn is_admin() -> impl Signal<Item = bool> {
+    user_role().signal().map(|role| role == Some(Role::Admin))
+}
+
 // ------ ------
 //   Commands
 // ------ ------
@@ -64,14 +94,52 @@ pub fn set_page_id(new_page_id: PageId) {
     page_id().set_neq(new_page_id);
 }
 
-pub fn log_in(name: String) {
-    logged_user().set(Some(name));
+// Pick up the login from before a reload, the backend still knows its token (unless it has
+// restarted or the token has expired since, in which case the first message we send is refused
+// and we log in again)
+pub fn load_login() {
+    if let Some(Ok(login)) = local_storage().get::<LoginAccepted>(LOGIN_STORAGE_KEY) {
+        logged_user().set(Some(login.username));
+        auth_token().set(Some(login.token));
+        user_role().set(Some(login.role));
+    }
+}
+
+pub fn log_in(login: LoginAccepted) {
+    if let Err(error) = local_storage().insert(LOGIN_STORAGE_KEY, &login) {
+        eprintln!("Failed to store the login: {:?}", error);
+    }
+    logged_user().set(Some(login.username));
+    auth_token().set(Some(login.token));
+    user_role().set(Some(login.role));
     router().go(previous_route().unwrap_or(Route::Root));
 }
 
 pub fn log_out() {
+    Task::start(async {
+        // Still carrying the token, so the backend knows which login to end
+        let result = event_edit_page::connection()
+            .send_up_msg(UpMsg::Logout)
+            .await;
+        if let Err(error) = result {
+            eprintln!("Failed to send logout message: {:?}", error);
+        }
+        forget_login();
+        router().go(Route::Root);
+    });
+}
+
+// The backend no longer accepts our token
+pub fn login_expired() {
+    forget_login();
+    router().go(Route::Login);
+}
+
+fn forget_login() {
+    local_storage().remove(LOGIN_STORAGE_KEY);
     logged_user().take();
-    router().go(Route::Root);
+    auth_token().take();
+    user_role().take();
 }
 
 // ------ ------
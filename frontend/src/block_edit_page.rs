@@ -1,5 +1,16 @@
-use crate::event_edit_page::{blocks, connection, original_text_as_p, play_block, player_element};
-use shared::{BlockEdited, BlockId, EventId, UpMsg};
+use crate::app::RenderBlock;
+use crate::event_edit_page::{
+    blocks, connection, event_loaded, has_role, loading, original_text_as_p, play_block,
+    player_element, speaker_labels, speaker_profiles,
+};
+use shared::audit::{format_utc, AuditEntry};
+use shared::ops::{diff_ops, WordOp};
+use shared::roles::Role;
+use shared::speakers::display_name;
+use shared::{
+    AuditQuery, AuditTrail, BlockId, EditConflict, EventId, ReassignBlockSpeaker,
+    RestoreBlockMessage, SplitBlockMessage, UpMsg, Word, WordsEdited,
+};
 use std::cmp::max;
 use zoon::{eprintln, named_color::*, println, *};
 
@@ -12,11 +23,47 @@ fn content() -> &'static Mutable<String> {
     Mutable::new(String::new())
 }
 
+// The block's version and words as they were when the text box was last loaded from it. What
+// the user types is diffed against these, however the block has changed since.
+#[static_ref]
+fn edit_base() -> &'static Mutable<Option<(u64, Vec<Word>)>> {
+    Mutable::new(None)
+}
+
+#[static_ref]
+fn this_event_id() -> &'static Mutable<Option<EventId>> {
+    Mutable::new(None)
+}
+
 #[static_ref]
 fn this_block_id() -> &'static Mutable<Option<BlockId>> {
     Mutable::new(None)
 }
 
+// Who speaks from the split onwards, blank to keep the block's speaker
+#[static_ref]
+fn new_speaker() -> &'static Mutable<String> {
+    Mutable::new(String::new())
+}
+
+// The block's audit entries, newest first
+#[static_ref]
+fn audit_trail() -> &'static MutableVec<AuditEntry> {
+    MutableVec::new()
+}
+
+// The last edit of ours the backend turned down, until we edit again
+#[static_ref]
+fn conflict() -> &'static Mutable<Option<EditConflict>> {
+    Mutable::new(None)
+}
+
+// Set when the user cleared the box, which we don't send; the block is deleted on the event page
+#[static_ref]
+fn is_emptied() -> &'static Mutable<bool> {
+    Mutable::new(false)
+}
+
 // ------ ------
 //   Commands
 // ------ ------
@@ -25,34 +72,228 @@ fn text_change_handler(text: String) {
     content().set(text);
 }
 
-fn text_blur_handler() {
-    if let Some(block_id) = this_block_id().get() {
+fn set_new_speaker(speaker: String) {
+    new_speaker().set(speaker);
+}
+
+// Cut the block before word `at_word_index`, everything from there on becoming a new block below
+fn split_block(id: BlockId, at_word_index: usize) {
+    let event_id = match this_event_id().get() {
+        Some(event_id) => event_id,
+        None => return,
+    };
+    let version = {
         let blocks = blocks().lock_ref();
-        let found = blocks.iter().find(|b| b.id == block_id);
-        match found {
-            Some(block) => {
-                let text = content().lock_ref().clone();
-                block_edit_message(block_id, block.speaker.clone(), text);
+        match blocks.iter().find(|b| b.id == id) {
+            Some(block) => block.version.get(),
+            None => {
+                eprintln!("Block {} not found to split!", id);
+                return;
+            }
+        }
+    };
+    let speaker = new_speaker().get_cloned().trim().to_string();
+    let new_speaker = (!speaker.is_empty()).then(|| speaker);
+    println!(
+        "Send split message for block {} at word {}",
+        id, at_word_index
+    );
+    Task::start(async move {
+        let result = connection()
+            .send_up_msg(UpMsg::SplitBlock(SplitBlockMessage {
+                event_id,
+                id,
+                version,
+                at_word_index,
+                new_speaker,
+            }))
+            .await;
+        if let Err(error) = result {
+            eprintln!("Failed to send block split message: {:?}", error);
+        }
+    });
+}
+
+fn reassign_speaker(id: BlockId, speaker: String) {
+    let event_id = match this_event_id().get() {
+        Some(event_id) => event_id,
+        None => return,
+    };
+    println!(
+        "Send reassign message for block {} to speaker {}",
+        id, speaker
+    );
+    Task::start(async move {
+        let result = connection()
+            .send_up_msg(UpMsg::ReassignBlockSpeaker(ReassignBlockSpeaker {
+                event_id,
+                id,
+                speaker,
+            }))
+            .await;
+        if let Err(error) = result {
+            eprintln!("Failed to send reassign speaker message: {:?}", error);
+        }
+    });
+}
+
+pub fn set_audit_trail(trail: AuditTrail) {
+    if this_event_id().get() != Some(trail.event_id) || this_block_id().get() != trail.block_id {
+        return;
+    }
+    audit_trail()
+        .lock_mut()
+        .replace_cloned(trail.entries.into_iter().rev().collect());
+}
+
+// Someone (maybe us) changed a block, if it's the one we're showing its history has grown
+pub fn block_changed(id: BlockId) {
+    if this_block_id().get() == Some(id) {
+        request_audit_trail();
+    }
+}
+
+fn request_audit_trail() {
+    if let (Some(event_id), Some(block_id)) = (this_event_id().get(), this_block_id().get()) {
+        Task::start(async move {
+            let result = connection()
+                .send_up_msg(UpMsg::ListAudit(AuditQuery {
+                    event_id,
+                    block_id: Some(block_id),
+                }))
+                .await;
+            if let Err(error) = result {
+                eprintln!("Failed to send audit trail request: {:?}", error);
+            }
+        });
+    }
+}
+
+fn restore_block(seq: u64) {
+    if let (Some(event_id), Some(id)) = (this_event_id().get(), this_block_id().get()) {
+        println!("Send restore message for block {} to entry {}", id, seq);
+        Task::start(async move {
+            let result = connection()
+                .send_up_msg(UpMsg::RestoreBlock(RestoreBlockMessage {
+                    event_id,
+                    id,
+                    seq,
+                }))
+                .await;
+            if let Err(error) = result {
+                eprintln!("Failed to send restore block message: {:?}", error);
             }
+        });
+    }
+}
+
+// Somebody changed the same words first. The block has already been brought up to date; put its
+// text back in front of the user so they can redo their change on top of it.
+pub fn show_conflict(edit_conflict: EditConflict) {
+    if this_block_id().get() != Some(edit_conflict.id) {
+        return;
+    }
+    let blocks = blocks().lock_ref();
+    if let Some(block) = blocks.iter().find(|b| b.id == edit_conflict.id) {
+        load_block_text(block);
+    }
+    conflict().set(Some(edit_conflict));
+}
+
+// The box shows the block as it is now, whatever came in while it didn't have the focus
+fn text_focus_handler() {
+    if let Some(block_id) = this_block_id().get() {
+        let blocks = blocks().lock_ref();
+        match blocks.iter().find(|b| b.id == block_id) {
+            Some(block) => load_block_text(block),
             None => eprintln!("Block {} not found!", block_id),
         }
     }
 }
 
+fn text_blur_handler() {
+    let (base_version, base_words) = match edit_base().replace(None) {
+        Some(base) => base,
+        None => return,
+    };
+    if content().lock_ref().trim().is_empty() {
+        let text: Vec<&str> = base_words.iter().map(|word| word.text.as_str()).collect();
+        content().set(text.join(" "));
+        is_emptied().set(true);
+        return;
+    }
+    if let (Some(event_id), Some(block_id)) = (this_event_id().get(), this_block_id().get()) {
+        // Only what changed goes up, so edits elsewhere in the block survive this one
+        let ops = diff_ops(&base_words, &content().lock_ref());
+        if !ops.is_empty() {
+            conflict().set(None);
+            is_emptied().set(false);
+            words_edited_message(event_id, block_id, base_version, ops);
+        }
+    }
+}
+
+fn load_block_text(block: &RenderBlock) {
+    edit_base().set(Some((
+        block.version.get(),
+        block.raw_words.lock_ref().to_vec(),
+    )));
+    content().set(block.full_text.get_cloned());
+}
+
 // ------ ------
 //     View
 // ------ ------
 
-pub fn page(_event_id: EventId, block_id: BlockId) -> impl Element {
+pub fn page(event_id: EventId, block_id: BlockId) -> impl Element {
+    this_event_id().set(Some(event_id));
     this_block_id().set(Some(block_id));
+    conflict().set(None);
+    is_emptied().set(false);
+    edit_base().set(None);
+    new_speaker().set(String::new());
+    audit_trail().lock_mut().clear();
+    request_audit_trail();
     Column::new()
         .s(Spacing::new(15))
         .item(player_element())
-        .item(corrected_text(block_id))
-        .item(original_text(block_id))
+        .item_signal(event_loaded().map(move |is_loaded| {
+            if is_loaded {
+                block_editor(block_id).into_raw_element()
+            } else {
+                loading().into_raw_element()
+            }
+        }))
         .item(back_button())
 }
 
+// Only built once the event's blocks are in, it starts from the block as it is then
+fn block_editor(block_id: BlockId) -> impl Element {
+    Column::new()
+        .s(Spacing::new(15))
+        .item_signal(conflict().signal_ref(|conflict| conflict.as_ref().map(conflict_alert)))
+        .item_signal(
+            is_emptied()
+                .signal()
+                .map(|is_emptied| is_emptied.then(emptied_alert)),
+        )
+        .item_signal(has_role(Role::Editor).map(move |can_edit| {
+            if can_edit {
+                corrected_text(block_id).into_raw_element()
+            } else {
+                corrected_text_as_p(block_id).into_raw_element()
+            }
+        }))
+        .item(original_text(block_id))
+        .item_signal(
+            has_role(Role::Editor).map(move |can_edit| can_edit.then(|| speaker_choices(block_id))),
+        )
+        .item_signal(
+            has_role(Role::Editor).map(move |can_edit| can_edit.then(|| split_here(block_id))),
+        )
+        .item(history_panel())
+}
+
 fn corrected_text(id: BlockId) -> impl Element {
     let mut num_rows: u32 = 6;
     let blocks = blocks().lock_ref();
@@ -68,27 +309,68 @@ fn corrected_text(id: BlockId) -> impl Element {
             "".to_string()
         }
     };
+    // Start from the block's text, or leaving the box untouched would read as clearing it
+    content().set(text);
 
     RawHtmlEl::new("div").attr("class", "col-md-8").child(
         TextArea::new()
             .s(Width::fill())
             .s(Height::new(num_rows * 12)) //
             .s(Padding::all(4))
-            .text(text)
+            .text_signal(content().signal_cloned())
             .on_change(text_change_handler)
+            .update_raw_el(|raw_el| raw_el.event_handler(|_: events::Focus| text_focus_handler()))
             .on_blur(text_blur_handler)
             .label_hidden("Corrected text"),
     )
 }
 
-fn block_edit_message(block_id: BlockId, speaker: String, text: String) {
-    println!("Send block edited message for block {}", block_id);
+// Users who can't edit the block only read it
+fn corrected_text_as_p(id: BlockId) -> impl Element {
+    let text = match blocks().lock_ref().iter().find(|b| b.id == id) {
+        Some(block) => block.full_text.signal_cloned(),
+        None => {
+            eprintln!("Block {} not found to display!", id);
+            return RawHtmlEl::new("p");
+        }
+    };
+    RawHtmlEl::new("p")
+        .attr("class", "col-md-8")
+        .child_signal(text)
+}
+
+fn conflict_alert(conflict: &EditConflict) -> impl Element {
+    let message = if conflict.current.is_some() {
+        "Someone else changed these words while you were editing, so your change wasn't saved. \
+         The text below is the block as it is now, make your change again."
+    } else {
+        "This block was deleted or merged while you were editing, your change wasn't saved."
+    };
+    RawHtmlEl::new("div")
+        .attr("class", "alert alert-warning col-md-8")
+        .attr("role", "alert")
+        .child(message)
+}
+
+fn emptied_alert() -> impl Element {
+    RawHtmlEl::new("div")
+        .attr("class", "alert alert-info col-md-8")
+        .attr("role", "alert")
+        .child(
+            "A block can't be left without words, so the text was put back. To remove the whole \
+             block, use its delete button (the ✕) on the event page.",
+        )
+}
+
+fn words_edited_message(event_id: EventId, block_id: BlockId, base_version: u64, ops: Vec<WordOp>) {
+    println!("Send words edited message for block {}", block_id);
     Task::start(async move {
         let result = connection()
-            .send_up_msg(UpMsg::EditBlock(BlockEdited {
+            .send_up_msg(UpMsg::EditWords(WordsEdited {
+                event_id,
                 id: block_id,
-                speaker: speaker.to_string(),
-                corrected_text: text,
+                base_version,
+                ops,
             }))
             .await;
         if let Err(error) = result {
@@ -125,6 +407,138 @@ fn original_text(id: BlockId) -> impl Element {
     }
 }
 
+// The event's speakers, the block's own picked out. Picking another gives it the whole block.
+fn speaker_choices(id: BlockId) -> impl Element {
+    let speaker = match blocks().lock_ref().iter().find(|b| b.id == id) {
+        Some(block) => block.speaker.clone(),
+        None => {
+            println!("Block {} not found to reassign!", id);
+            return RawHtmlEl::new("div");
+        }
+    };
+    let choices = map_ref! {
+        let current = speaker.signal_cloned(),
+        let labels = speaker_labels(),
+        let profiles = speaker_profiles().signal_cloned() =>
+        labels
+            .iter()
+            .map(|label| (label.clone(), display_name(profiles, label), label == current))
+            .collect::<Vec<(String, String, bool)>>()
+    };
+    RawHtmlEl::new("div")
+        .attr("class", "col-md-8")
+        .child(RawHtmlEl::new("h4").child("Speaker"))
+        .child_signal(choices.map(move |choices| {
+            RawHtmlEl::new("div").children(choices.into_iter().map(
+                move |(label, name, is_current)| {
+                    let class = if is_current {
+                        "btn btn-primary"
+                    } else {
+                        "btn btn-default"
+                    };
+                    RawHtmlEl::new("button")
+                        .attr("class", class)
+                        .attr("type", "button")
+                        .event_handler(move |_: events::Click| {
+                            if !is_current {
+                                reassign_speaker(id, label.clone());
+                            }
+                        })
+                        .child(name)
+                },
+            ))
+        }))
+}
+
+// The block's words, each (bar the first) a place the block can be split
+fn split_here(id: BlockId) -> impl Element {
+    let blocks = blocks().lock_ref();
+    let words = match blocks.iter().find(|b| b.id == id) {
+        Some(block) => block.raw_words.signal_vec_cloned().enumerate(),
+        None => {
+            println!("Block {} not found to split!", id);
+            return RawHtmlEl::new("div");
+        }
+    };
+    RawHtmlEl::new("div")
+        .attr("class", "col-md-8")
+        .child(RawHtmlEl::new("h4").child("Split here"))
+        .child(RawHtmlEl::new("p").child(
+            "Click the word the second speaker starts with. It and everything after it \
+             become a new block below this one.",
+        ))
+        .child(
+            TextInput::new()
+                .s(Padding::all(4))
+                .label_hidden("Speaker of the new block")
+                .placeholder(Placeholder::new(
+                    "Speaker of the new block (blank keeps this one)",
+                ))
+                .text_signal(new_speaker().signal_cloned())
+                .on_change(set_new_speaker),
+        )
+        .child(
+            RawHtmlEl::new("p").children_signal_vec(words.map(move |(index, word)| {
+                let text = format!("{} ", word.text);
+                match index.get() {
+                    Some(0) | None => RawHtmlEl::new("span").child(text),
+                    Some(_) => RawHtmlEl::new("a")
+                        .attr("title", "Split the block before this word")
+                        .event_handler(move |_: events::Click| {
+                            if let Some(at_word_index) = index.get() {
+                                split_block(id, at_word_index);
+                            }
+                        })
+                        .child(text),
+                }
+            })),
+        )
+}
+
+// Who changed the block and how, newest first, each version one click from coming back
+fn history_panel() -> impl Element {
+    RawHtmlEl::new("div")
+        .attr("class", "col-md-8")
+        .child(RawHtmlEl::new("h4").child("History"))
+        .child(
+            RawHtmlEl::new("table")
+                .attr("class", "table table-condensed")
+                .child(
+                    RawHtmlEl::new("tbody")
+                        .children_signal_vec(audit_trail().signal_vec_cloned().map(audit_row)),
+                ),
+        )
+}
+
+fn audit_row(entry: AuditEntry) -> impl Element {
+    let seq = entry.seq;
+    let is_visible = entry.after.is_visible;
+    let (label, text) = (entry.after.speaker, entry.after.text);
+    // Named as the speaker is named now, the entry only has the label
+    let text = speaker_profiles().signal_ref(move |profiles| {
+        if is_visible {
+            format!("{}: {}", display_name(profiles, &label), text)
+        } else {
+            "(hidden)".to_string()
+        }
+    });
+    RawHtmlEl::new("tr")
+        .child(RawHtmlEl::new("td").child(format_utc(entry.at_ms)))
+        .child(RawHtmlEl::new("td").child(entry.user))
+        .child(RawHtmlEl::new("td").child(format!("{:?}", entry.action)))
+        .child(RawHtmlEl::new("td").child_signal(text))
+        .child(
+            RawHtmlEl::new("td").child_signal(has_role(Role::Editor).map(move |can_edit| {
+                (can_edit && is_visible).then(|| {
+                    RawHtmlEl::new("a")
+                        .attr("title", "Put the block back to this version")
+                        .event_handler(move |_: events::Click| restore_block(seq))
+                        .child("Restore")
+                })
+            })),
+        )
+}
+
 fn back_button() -> impl Element {
     let (hovered, hovered_signal) = Mutable::new_and_signal(false);
     Button::new()
@@ -1,14 +1,48 @@
-use crate::app::RenderBlock;
+use crate::app::{self, RenderBlock};
+use crate::block_edit_page;
+use crate::events_page;
+use crate::login_page;
 use crate::router::{router, Route};
-use shared::{BlockId, BlockMessage, EventChoiceMessage, EventId, Word};
+use shared::ops::apply_ops;
+use shared::roles::Role;
+use shared::speakers::{
+    display_name, name_key, PersonId, SpeakerLibrary, SpeakerProfile, SpeakerProfiles,
+};
+use shared::{
+    BlockApproval, BlockId, BlockMessage, EventChoiceMessage, EventId, EventSnapshot, EventUpdate,
+    MergeBlockMessage, NewPerson, SpeakerLink, SpeakerRenamed, Word,
+};
 use shared::{DownMsg, UpMsg};
+use std::collections::BTreeSet;
 use std::ops::Not;
 use std::sync::Arc;
-use zoon::futures_signals::signal_vec::MutableVecLockRef;
+use zoon::futures_signals::signal_vec::{MutableVecLockMut, MutableVecLockRef};
 use zoon::{
     eprintln, println, static_ref, Connection, Mutable, MutableVec, RawHtmlEl, Signal, Task, *,
 };
 
+// People from the speaker library offered for a label that isn't linked to anyone
+const MAX_SUGGESTIONS: usize = 3;
+
+// ------ ------
+//     Types
+// ------ ------
+
+// One label's row in the speakers panel
+#[derive(Clone, PartialEq)]
+struct SpeakerEntry {
+    label: String,
+    profile: SpeakerProfile,
+    suggestions: Vec<SuggestedPerson>,
+}
+
+#[derive(Clone, PartialEq)]
+struct SuggestedPerson {
+    id: PersonId,
+    text: String, // The name and why they're suggested
+    notes: Option<String>,
+}
+
 // ------ ------
 // Reference reading around Mutable and signals
 // https://docs.rs/futures-signals/0.3.24/futures_signals/tutorial/index.html
@@ -32,13 +66,120 @@ fn event_id() -> &'static Mutable<Option<EventId>> {
     Mutable::new(None)
 }
 
+// What we may do in the event, as the backend told us when we chose it. Only looking until then.
+#[static_ref]
+fn event_role() -> &'static Mutable<Role> {
+    Mutable::new(Role::Viewer)
+}
+
+// Its first snapshot has come, a resync doesn't count
+#[static_ref]
+fn is_event_loaded() -> &'static Mutable<bool> {
+    Mutable::new(false)
+}
+
+// The number of the event's latest update we've applied, None until its snapshot arrives
+#[static_ref]
+fn last_seq() -> &'static Mutable<Option<u64>> {
+    Mutable::new(None)
+}
+
+// Updates that overtook the snapshot on its way, applied after it unless it already has them
+#[static_ref]
+fn early_updates() -> &'static Mutable<Vec<EventUpdate>> {
+    Mutable::new(Vec::new())
+}
+
+// Who each diarization label in the event is
+#[static_ref]
+pub fn speaker_profiles() -> &'static Mutable<SpeakerProfiles> {
+    Mutable::new(SpeakerProfiles::new())
+}
+
+// Only fetched for editors, they're the ones who link speakers to it
+#[static_ref]
+fn speaker_library() -> &'static Mutable<Option<SpeakerLibrary>> {
+    Mutable::new(None)
+}
+
+#[static_ref]
+fn audio_url() -> &'static Mutable<Option<String>> {
+    Mutable::new(None)
+}
+
+#[static_ref]
+fn is_event_finished() -> &'static Mutable<bool> {
+    Mutable::new(false)
+}
+
+// The live block still being transcribed, shown below the finished ones until it's created
+#[static_ref]
+fn partial_block() -> &'static Mutable<Option<BlockMessage>> {
+    Mutable::new(None)
+}
+
 #[static_ref]
 pub fn connection() -> &'static Connection<UpMsg, DownMsg> {
-    Connection::new(|down_msg, cor_id| match down_msg {
+    Connection::new(handle_down_msg).auth_token_getter(app::connection_auth_token)
+}
+
+fn handle_down_msg(down_msg: DownMsg, cor_id: CorId) {
+    match down_msg {
+        // The backend only sends us the event we chose, but some may still be on their way from
+        // the one before
+        down_msg if !is_current_event(down_msg.event_id()) => {
+            println!("Ignoring a message for event {:?}", down_msg.event_id());
+        }
+        DownMsg::LoggedIn(login) => app::log_in(login),
+        DownMsg::LoginRejected(reason) => login_page::set_error(reason),
+        DownMsg::AuthRequired => {
+            eprintln!("The backend wants us to log in (again)");
+            app::login_expired();
+        }
+        DownMsg::PermissionDenied(reason) => {
+            eprintln!("The backend refused: {}", reason);
+            alert(&reason);
+        }
+        DownMsg::EventRole(msg) => {
+            event_role().set(msg.role);
+            if msg.role >= Role::Editor {
+                request_speaker_library();
+            }
+        }
+        DownMsg::Accounts(accounts) => events_page::set_accounts(accounts),
+        DownMsg::EventList(events) => events_page::set_events(events),
         DownMsg::EventSelected(msg) => {
             println!("DownMsg Choose event {:?}, cor_id: {}", msg.id, cor_id);
         }
+        DownMsg::EventSnapshot(snapshot) => {
+            apply_snapshot(snapshot);
+            let updates = std::mem::take(&mut *early_updates().lock_mut());
+            for update in updates {
+                handle_down_msg(DownMsg::EventUpdate(update), cor_id);
+            }
+        }
+        DownMsg::EventUpdate(update) => match last_seq().get() {
+            // The snapshot on its way may not have it yet, keep it until the snapshot is here
+            None => early_updates().lock_mut().push(update),
+            // Sent before the snapshot we have, which has it
+            Some(seq) if update.seq <= seq => {}
+            Some(seq) if update.seq == seq + 1 => {
+                last_seq().set(Some(update.seq));
+                handle_down_msg(*update.down_msg, cor_id);
+            }
+            Some(seq) => {
+                eprintln!(
+                    "Missed updates {} to {}, resyncing",
+                    seq + 1,
+                    update.seq - 1
+                );
+                resync();
+            }
+        },
+        DownMsg::BlockPartial(msg) => partial_block().set(Some(msg)),
         DownMsg::BlockCreated(msg) => {
+            // The live block is always the next one created (its id can change if a split took it)
+            partial_block().set(None);
             let mut blocks = blocks().lock_mut();
             match blocks.iter().find(|block| block.id == msg.id) {
                 Some(block) => {
@@ -46,81 +187,163 @@ pub fn connection() -> &'static Connection<UpMsg, DownMsg> {
                 }
                 None => {
                     println!("Create block {}", msg.id);
-                    let raw_words = MutableVec::new();
-                    for word in msg.words {
-                        raw_words.lock_mut().push_cloned(word)
-                    }
-                    let full_text = build_full_text(raw_words.lock_ref());
-                    let block = RenderBlock {
-                        id: msg.id,
-                        speaker: msg.speaker,
-                        raw_words,
-                        full_text: Mutable::new(full_text),
-                        is_visible: Mutable::new(true),
-                    };
-                    blocks.push_cloned(Arc::new(block));
+                    // Blocks can arrive out of order, slot this one in by id
+                    let idx = blocks
+                        .iter()
+                        .position(|block| block.id > msg.id)
+                        .unwrap_or(blocks.len());
+                    blocks.insert_cloned(idx, Arc::new(render_block(msg)));
                     load_audio();
                 }
             }
         }
-        DownMsg::BlockEdited(msg) => {
-            println!("Edit block {}", msg.id);
+        DownMsg::WordsEdited(msg) => {
+            println!("Edit words of block {}", msg.id);
             let blocks = blocks().lock_ref();
             match blocks.iter().find(|block| block.id == msg.id) {
+                // Edits arrive in the order the backend applied them, so we should always be at
+                // the version this one was applied to. If we aren't, our copy has drifted.
+                Some(block) if block.version.get() != msg.base_version => {
+                    eprintln!(
+                        "Block {} is at version {}, cannot apply an edit of version {}, resyncing",
+                        msg.id,
+                        block.version.get(),
+                        msg.base_version
+                    );
+                    resync();
+                }
                 Some(block) => {
-                    block.full_text.replace(msg.corrected_text);
+                    let mut words = block.raw_words.lock_ref().to_vec();
+                    if apply_ops(&mut words, &msg.ops) {
+                        block.version.set(msg.base_version + 1);
+                        block.raw_words.lock_mut().replace_cloned(words);
+                        block
+                            .full_text
+                            .set(build_full_text(block.raw_words.lock_ref()));
+                        block_edit_page::block_changed(msg.id);
+                    } else {
+                        eprintln!("Edit doesn't fit block {}, resyncing", msg.id);
+                        resync();
+                    }
+                }
+                None => println!("No block {:?} found to edit", msg.id),
+            }
+        }
+        DownMsg::EditConflict(conflict) => {
+            println!("Edit of block {} conflicts", conflict.id);
+            if let Some(current) = &conflict.current {
+                let blocks = blocks().lock_ref();
+                if let Some(block) = blocks.iter().find(|block| block.id == current.id) {
+                    update_block(block, current.clone());
                 }
-                None => println!("No block {:?} found to update", msg.id),
             }
+            block_edit_page::show_conflict(conflict);
         }
         DownMsg::BlockMergedWithAbove(msg) => {
-            println!("Merge block {} with the block above", msg.id);
-
-            // Find our position in the blocks (if we exist)
-            let pos = blocks()
-                .lock_ref()
-                .iter()
-                .position(|block| block.id == msg.id);
-
-            // Given the position of the block we want to merge above, extract its text, append above, then delete
-            // ... only merge blocks if the speakers in both blocks are the same
-            match pos {
-                Some(0) => println!("Cannot merge first element, there's nothing above us"),
-                Some(idx) => {
-                    let blocks = blocks().lock_ref();
-                    match blocks.iter().find(|block| block.id == msg.id) {
-                        None => println!(" ... no block {} found to merge above", msg.id),
-                        Some(_) => {
-                            let mut prev_idx = idx - 1;
-                            // walk back blocks until we find the first visible block above us
-                            while !*blocks[prev_idx].is_visible.lock_ref() {
-                                if prev_idx != 0 {
-                                    prev_idx -= 1;
-                                }
-                            }
-                            blocks[idx].is_visible.set(false);
-                            if blocks[prev_idx].speaker != blocks[idx].speaker {
-                                eprintln!("Cannot merge different speakers");
-                            } else {
-                                for word in msg.words {
-                                    blocks[prev_idx].raw_words.lock_mut().push_cloned(word);
-                                }
-                                let full_text =
-                                    build_full_text(blocks[prev_idx].raw_words.lock_ref());
-                                blocks[prev_idx]
-                                    .full_text
-                                    .lock_mut()
-                                    .replace_range(.., full_text.as_str());
-                            }
-                        }
+            println!("Merge block {} into block {}", msg.id, msg.into.id);
+            let blocks = blocks().lock_ref();
+            if let Some(block) = blocks.iter().find(|block| block.id == msg.id) {
+                block.is_visible.set(false);
+            }
+            match blocks.iter().find(|block| block.id == msg.into.id) {
+                Some(above) => {
+                    block_edit_page::block_changed(above.id);
+                    update_block(above, msg.into);
+                }
+                None => eprintln!("No block {} found to merge into", msg.into.id),
+            }
+        }
+        DownMsg::MergeRejected(msg) => {
+            eprintln!("Merge of block {} rejected: {}", msg.id, msg.reason);
+            alert(&msg.reason);
+        }
+        DownMsg::BlockSplit(msg) => {
+            println!("Split block {} at word {}", msg.id, msg.at_word_index);
+            let mut blocks = blocks().lock_mut();
+            match blocks.iter().find(|block| block.id == msg.id).cloned() {
+                Some(block) => {
+                    block.raw_words.lock_mut().truncate(msg.at_word_index);
+                    block.version.replace_with(|version| *version + 1);
+                    block
+                        .full_text
+                        .set(build_full_text(block.raw_words.lock_ref()));
+                    blocks.push_cloned(Arc::new(render_block(msg.new_block)));
+                    reorder_blocks(&mut blocks, &msg.order);
+                    block_edit_page::block_changed(msg.id);
+                }
+                None => println!("No block {:?} found to split", msg.id),
+            }
+        }
+        DownMsg::BlockOrder(msg) => reorder_blocks(&mut blocks().lock_mut(), &msg.order),
+        DownMsg::BlocksRestored(msg) => {
+            let mut blocks = blocks().lock_mut();
+            for restored in msg.blocks {
+                block_edit_page::block_changed(restored.id);
+                match blocks.iter().find(|block| block.id == restored.id).cloned() {
+                    Some(block) => {
+                        update_block(&block, restored);
+                        block.is_visible.set(true);
                     }
+                    None => blocks.push_cloned(Arc::new(render_block(restored))),
                 }
-                None => eprintln!("No current block {} found, cannot merge above", msg.id),
-            };
+            }
+            for id in msg.removed {
+                if let Some(block) = blocks.iter().find(|block| block.id == id) {
+                    block.is_visible.set(false);
+                }
+            }
+            reorder_blocks(&mut blocks, &msg.order);
         }
-
+        DownMsg::AuditTrail(trail) => block_edit_page::set_audit_trail(trail),
+        DownMsg::HistoryRejected(msg) => {
+            eprintln!("Event {}: {}", msg.event_id, msg.reason);
+            alert(&msg.reason);
+        }
+        DownMsg::BlockApproved(msg) => {
+            let blocks = blocks().lock_ref();
+            match blocks.iter().find(|block| block.id == msg.id) {
+                Some(block) => block.approval.set(Some(msg)),
+                None => println!("No block {:?} found to approve", msg.id),
+            }
+        }
+        DownMsg::ApprovalRejected(msg) => {
+            eprintln!("Approval of block {} rejected: {}", msg.id, msg.reason);
+            alert(&msg.reason);
+        }
+        DownMsg::SpeakerRenamed(msg) => {
+            println!("Speaker {} is now {}", msg.label, msg.profile.name);
+            speaker_profiles().lock_mut().insert(msg.label, msg.profile);
+        }
+        DownMsg::BlockSpeakerReassigned(msg) => {
+            println!("Block {} is now speaker {}", msg.id, msg.speaker);
+            let blocks = blocks().lock_ref();
+            match blocks.iter().find(|block| block.id == msg.id) {
+                Some(block) => {
+                    block_edit_page::block_changed(msg.id);
+                    update_block(block, msg);
+                }
+                None => println!("No block {:?} found to reassign", msg.id),
+            }
+        }
+        DownMsg::SpeakerRejected(msg) => {
+            eprintln!("Event {}: {}", msg.event_id, msg.reason);
+            alert(&msg.reason);
+        }
+        DownMsg::SpeakerLibrary(library) => speaker_library().set(Some(library)),
         DownMsg::BlockDeleted(msg) => do_block_delete(msg.id),
-    })
+        DownMsg::EventFinished(msg) => {
+            println!("Event {} finished", msg.id);
+            is_event_finished().set(true);
+        }
+        // Forgotten, so opening another event (even one given its id) starts from scratch
+        DownMsg::EventDeleted(msg) => {
+            println!("Event {} deleted", msg.id);
+            event_id().set(None);
+            is_event_loaded().set_neq(false);
+            alert("This event has been deleted");
+            router().go(Route::EventRoot);
+        }
+    }
 }
 
 // ------ ------
@@ -131,11 +354,61 @@ fn blocks_exist() -> impl Signal<Item = bool> {
     blocks().signal_vec_cloned().is_empty().map(Not::not)
 }
 
+pub fn event_loaded() -> impl Signal<Item = bool> {
+    is_event_loaded().signal()
+}
+
+// The labels with a profile or a block, in order
+pub fn speaker_labels() -> impl Signal<Item = Vec<String>> {
+    map_ref! {
+        let profiles = speaker_profiles().signal_cloned(),
+        let block_labels = blocks()
+            .signal_vec_cloned()
+            .map_signal(|block| block.speaker.signal_cloned())
+            .to_signal_cloned() =>
+        profiles
+            .keys()
+            .chain(block_labels.iter())
+            .cloned()
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect::<Vec<String>>()
+    }
+    .dedupe_cloned()
+}
+
+// Messages about no event in particular are always for us
+fn is_current_event(id: Option<EventId>) -> bool {
+    id.map_or(true, |id| event_id().get() == Some(id))
+}
+
+pub fn has_role(required: Role) -> impl Signal<Item = bool> {
+    event_role().signal().map(move |role| role >= required)
+}
+
 // ------ ------
 //   Commands
 // ------ ------
-pub fn set_event_id(id: EventId) {
+// Subscribes to the event and asks for its snapshot, unless we already have them. Moving between
+// the event's pages keeps them.
+pub fn open_event(id: EventId) {
+    if event_id().get() == Some(id) && is_event_loaded().get() {
+        return;
+    }
+    if event_id().get() != Some(id) {
+        is_event_finished().set(false);
+        partial_block().set(None);
+        event_role().set(Role::Viewer);
+        last_seq().set(None);
+        early_updates().lock_mut().clear();
+        speaker_profiles().set(SpeakerProfiles::new());
+        speaker_library().set(None);
+        audio_url().set(None);
+        blocks().lock_mut().clear();
+        is_event_loaded().set_neq(false);
+    }
     event_id().set(Some(id));
+    choose_event(id);
 }
 
 pub fn edit_block(id: BlockId) {
@@ -153,19 +426,71 @@ pub fn edit_block(id: BlockId) {
     });
 }
 
-pub fn choose_event(event_id: Option<EventId>) {
-    if let Some(id) = event_id {
+fn choose_event(id: EventId) {
+    Task::start(async move {
+        let result = connection()
+            .send_up_msg(UpMsg::ChooseEvent(EventChoiceMessage { id }))
+            .await;
+        if let Err(error) = result {
+            eprintln!("Failed to send choose event message: {:?}.", error);
+        }
+    });
+}
+
+// Throws away what we have for a fresh snapshot, updates are ignored until it comes
+fn resync() {
+    last_seq().set(None);
+    if let Some(id) = event_id().get() {
+        Task::start(async move {
+            let result = connection()
+                .send_up_msg(UpMsg::Resync(EventChoiceMessage { id }))
+                .await;
+            if let Err(error) = result {
+                eprintln!("Failed to send resync message: {:?}.", error);
+            }
+        });
+    }
+}
+
+// Undo and redo only ever touch this user's own deletes, merges and splits
+fn undo() {
+    send_history_message(UpMsg::Undo);
+}
+
+fn redo() {
+    send_history_message(UpMsg::Redo);
+}
+
+fn send_history_message(up_msg: fn(EventChoiceMessage) -> UpMsg) {
+    if let Some(id) = event_id().get() {
         Task::start(async move {
             let result = connection()
-                .send_up_msg(UpMsg::ChooseEvent(EventChoiceMessage { id }))
+                .send_up_msg(up_msg(EventChoiceMessage { id }))
                 .await;
             if let Err(error) = result {
-                eprintln!("Failed to send choose event message: {:?}.", error);
+                eprintln!("Failed to send undo/redo message: {:?}.", error);
             }
         });
     }
 }
 
+// Ctrl+Z undoes, Ctrl+Shift+Z or Ctrl+Y redoes (Cmd on a Mac). In the speaker inputs they're
+// the input's own undo and redo.
+fn history_shortcut(event: events::KeyDown) {
+    let event = event.raw_event;
+    let can_edit = event_role().get() >= Role::Editor;
+    if !can_edit || !(event.ctrl_key() || event.meta_key()) || is_typing(&event) {
+        return;
+    }
+    match event.key().to_lowercase().as_str() {
+        "z" if event.shift_key() => redo(),
+        "z" => undo(),
+        "y" => redo(),
+        _ => return,
+    }
+    event.prevent_default();
+}
+
 fn select_block(id: BlockId) {
     // TODO: This assigns the `current` class to the selected block, but we're not styling on that class yet
     selected_block().set(Some(id));
@@ -173,17 +498,14 @@ fn select_block(id: BlockId) {
 
 fn remove_block(id: BlockId) {
     println!("Remove block {}.", id);
-    Task::start(async move {
-        let result = connection()
-            .send_up_msg(UpMsg::DeleteBlock(BlockMessage {
-                id,
-                speaker: "n/a".to_string(), // TODO: Create a BlockIdOnlyMessage (but w/ better name)
-                words: vec![],
-            }))
-            .await;
-        if let Err(error) = result {
-            eprintln!("Failed to send delete block message: {:?}.", error);
-        }
+    send_for_event("delete block", move |event_id| {
+        UpMsg::DeleteBlock(BlockMessage {
+            event_id,
+            id,
+            version: 0,                 // Deleting doesn't care which version it deletes
+            speaker: "n/a".to_string(), // TODO: Create a BlockIdOnlyMessage (but w/ better name)
+            words: vec![],
+        })
     });
 }
 
@@ -194,12 +516,16 @@ pub fn play_block(id: BlockId) {
     match found {
         None => eprintln!("No block {} found to play!", id),
         Some(block) => {
-            let mut start_time =
-                (block.raw_words.lock_ref().first().unwrap().start as f32 / 1000.0) - 1.0;
+            let words = block.raw_words.lock_ref();
+            let (first, last) = match (words.first(), words.last()) {
+                (Some(first), Some(last)) => (first, last),
+                _ => return eprintln!("Block {} has no words to play", id),
+            };
+            let mut start_time = (first.start as f32 / 1000.0) - 1.0;
             if start_time < 0.0 {
                 start_time = 0.0
             }
-            let end_time = block.raw_words.lock_ref().last().unwrap().start as f32 / 1000.0;
+            let end_time = last.start as f32 / 1000.0;
             let duration = end_time - start_time + 1.0;
             println!("Play block starting at {} for {}.", start_time, duration);
             play_from(start_time, duration);
@@ -207,34 +533,152 @@ pub fn play_block(id: BlockId) {
     }
 }
 
+// Sends the message `up_msg` builds for the open event. With no event open there's nothing to
+// send it about.
+fn send_for_event(name: &'static str, up_msg: impl FnOnce(EventId) -> UpMsg + 'static) {
+    let id = match event_id().get() {
+        Some(id) => id,
+        None => return eprintln!("No event open to send a {} message about", name),
+    };
+    Task::start(async move {
+        if let Err(error) = connection().send_up_msg(up_msg(id)).await {
+            eprintln!("Failed to send {} message: {:?}", name, error);
+        }
+    });
+}
+
 fn merge_above(id: BlockId) {
     println!("Merge above {}.", id);
-    Task::start(async move {
+    let (speaker, above_speaker) = {
         let blocks = blocks().lock_ref();
-        let found = blocks.iter().find(|b| b.id == id);
-
-        match found {
-            None => eprintln!("Merge block {} not found!", id),
-            Some(block) => {
-                let mut words_to_merge: Vec<Word> = Vec::new();
-                for word in block.raw_words.lock_ref().iter() {
-                    words_to_merge.push(word.clone());
-                }
-                let result = connection()
-                    .send_up_msg(UpMsg::MergeBlockAbove(BlockMessage {
-                        id,
-                        speaker: block.speaker.to_string(),
-                        words: words_to_merge,
-                    }))
-                    .await;
-                if let Err(error) = result {
-                    eprintln!("Failed to send merge above block message: {:?}", error);
-                }
+        let idx = match blocks.iter().position(|b| b.id == id) {
+            Some(idx) => idx,
+            None => {
+                eprintln!("Merge block {} not found!", id);
+                return;
+            }
+        };
+        match blocks[..idx].iter().rev().find(|b| b.is_visible.get()) {
+            Some(above) => (blocks[idx].speaker.get_cloned(), above.speaker.get_cloned()),
+            None => {
+                eprintln!("Cannot merge block {}, there's nothing above it", id);
+                return;
             }
         }
+    };
+    // The backend only merges different speakers when told who the merged block belongs to
+    let speaker = if speaker == above_speaker {
+        None
+    } else {
+        match ask_merged_speaker(&speaker, &above_speaker) {
+            Some(speaker) => Some(speaker),
+            None => return,
+        }
+    };
+    send_for_event("merge above block", move |event_id| {
+        UpMsg::MergeBlockAbove(MergeBlockMessage {
+            event_id,
+            id,
+            speaker,
+        })
+    });
+}
+
+// Sign off on the block as we're showing it
+fn approve_block(id: BlockId) {
+    let version = match blocks().lock_ref().iter().find(|block| block.id == id) {
+        Some(block) => block.version.get(),
+        None => {
+            eprintln!("No block {} to approve!", id);
+            return;
+        }
+    };
+    send_for_event("approve block", move |event_id| {
+        UpMsg::ApproveBlock(BlockApproval {
+            event_id,
+            id,
+            version,
+        })
+    });
+}
+
+fn rename_speaker(label: String, profile: SpeakerProfile) {
+    send_for_event("rename speaker", move |event_id| {
+        UpMsg::RenameSpeaker(SpeakerRenamed {
+            event_id,
+            label,
+            profile,
+        })
     });
 }
 
+fn request_speaker_library() {
+    if let Some(id) = event_id().get() {
+        Task::start(async move {
+            let result = connection()
+                .send_up_msg(UpMsg::ListSpeakerLibrary(EventChoiceMessage { id }))
+                .await;
+            if let Err(error) = result {
+                eprintln!("Failed to send list speaker library message: {:?}", error);
+            }
+        });
+    }
+}
+
+// None unlinks the label
+fn link_speaker(label: String, person_id: Option<PersonId>) {
+    send_for_event("link speaker", move |event_id| {
+        UpMsg::LinkSpeaker(SpeakerLink {
+            event_id,
+            label,
+            person_id,
+        })
+    });
+}
+
+// Puts the label's speaker in the library under the name typed for them, and links them
+fn add_person(label: String, name: String) {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        alert("Give the speaker a name first");
+        return;
+    }
+    let notes = match ask_person_notes(&name) {
+        Some(notes) => non_empty(notes),
+        None => return,
+    };
+    send_for_event("add person", move |event_id| {
+        UpMsg::AddPerson(NewPerson {
+            event_id,
+            label,
+            name,
+            notes,
+        })
+    });
+}
+
+// None if the user backs out, blank for no notes
+fn ask_person_notes(name: &str) -> Option<String> {
+    let message = format!(
+        "Add {} to the speaker library. Any notes about them (optional):",
+        name
+    );
+    web_sys::window()?.prompt_with_message(&message).ok()?
+}
+
+// None if the user backs out of the merge
+fn ask_merged_speaker(speaker: &str, above_speaker: &str) -> Option<String> {
+    let message = format!(
+        "This block is {} and the block above is {}. Merge them as speaker:",
+        speaker, above_speaker
+    );
+    let answer = web_sys::window()?
+        .prompt_with_message_and_default(&message, above_speaker)
+        .ok()??;
+    let answer = answer.trim().to_string();
+    (!answer.is_empty()).then(|| answer)
+}
+
 // ------ ------
 //     View
 // ------ ------
@@ -242,8 +686,16 @@ fn merge_above(id: BlockId) {
 pub fn page() -> impl Element {
     RawHtmlEl::new("div")
         .attr("class", "container")
+        .global_event_handler(history_shortcut)
         .child(jumbotron())
+        .child_signal(event_loaded().map(|is_loaded| (!is_loaded).then(loading)))
         .child(table())
+        .child_signal(
+            partial_block()
+                .signal_cloned()
+                .map(|block| block.map(partial_row)),
+        )
+        .child_signal(has_role(Role::Editor).map(|can_edit| can_edit.then(speakers_panel)))
 }
 
 fn jumbotron() -> impl Element {
@@ -253,7 +705,14 @@ fn jumbotron() -> impl Element {
             .child(
                 RawHtmlEl::new("div")
                     .attr("class", "col-md-6")
-                    .child(RawHtmlEl::new("h1").child("Jadili")),
+                    .child(RawHtmlEl::new("h1").child("Jadili"))
+                    .child_signal(is_event_finished().signal().map(|is_finished| {
+                        is_finished.then(|| {
+                            RawHtmlEl::new("span")
+                                .attr("class", "label label-success")
+                                .child("Transcription complete")
+                        })
+                    })),
             )
             .child(
                 RawHtmlEl::new("div")
@@ -264,35 +723,67 @@ fn jumbotron() -> impl Element {
     )
 }
 
+// No source until the event's snapshot says where its audio lives
 pub fn player_element() -> impl Element {
-    let event_name = match event_id().get() {
-        Some(id) => format!("event_{:04}", id),
-        None => "test_event".to_string(), // TODO! What should we do if event id isn't set???
-    };
     RawHtmlEl::new("div").child(
         RawHtmlEl::new("audio")
             .attr("id", "audio-player")
             .attr("class", "player col-md-5")
             .attr("controls", "")
             .attr("async", "")
-            .attr(
-                "src",
-                // FIXME: Set this to backblaze/jadili/events/<id>/__event_audio.wav
-                format!(
-                    "http://localhost:8080/_api/public/assets/{}/__event_audio.wav",
-                    event_name
-                )
-                .as_str(),
-            ),
+            // FIXME: Set this to backblaze/jadili/events/<id>/__event_audio.wav
+            .attr_signal("src", audio_url().signal_cloned()),
     )
 }
 
 fn action_buttons() -> impl Element {
     RawHtmlEl::new("div")
         .attr("class", "row")
-        .children([action_button("select-event", "Select Event", || {
-            choose_event(event_id().get())
-        })])
+        .child_signal(
+            has_role(Role::Editor)
+                .map(|can_edit| can_edit.then(|| action_button("undo", "Undo", undo))),
+        )
+        .child_signal(
+            has_role(Role::Editor)
+                .map(|can_edit| can_edit.then(|| action_button("redo", "Redo", redo))),
+        )
+        .child(download_button("srt", "Download SRT"))
+        .child(download_button("vtt", "Download WebVTT"))
+        .child(download_button("txt", "Download Text"))
+        .child(download_button("md", "Download Markdown"))
+        .child(download_button("docx", "Download DOCX"))
+}
+
+// The backend builds the exports from the corrected blocks, we only ask for them with our login
+fn download_button(format: &'static str, title: &'static str) -> impl Element {
+    RawHtmlEl::new("div")
+        .attr("class", "col-sm-3 smallpad")
+        .child(
+            RawHtmlEl::new("button")
+                .attr("class", "btn btn-default btn-block")
+                .attr("type", "button")
+                .event_handler(move |_: events::Click| download(format))
+                .child(title),
+        )
+}
+
+fn download(format: &str) {
+    let id = match event_id().get() {
+        Some(id) => id,
+        None => return,
+    };
+    let token = match app::login_token() {
+        Some(token) => token,
+        None => return app::login_expired(),
+    };
+    let on_done = Closure::wrap(Box::new(|status: u16| match status {
+        200 => (),
+        401 => app::login_expired(),
+        _ => alert("The download failed, try again in a moment"),
+    }) as Box<dyn FnMut(u16)>);
+    download_export(&format!("/export/{}/{}", id, format), &token, &on_done);
+    // JS calls it long after we've returned, hand it over for good
+    on_done.forget();
 }
 
 fn action_button(id: &'static str, title: &'static str, on_click: fn()) -> impl Element {
@@ -320,6 +811,44 @@ fn table() -> impl Element {
         }))
 }
 
+pub fn loading() -> impl Element {
+    RawHtmlEl::new("p")
+        .attr("class", "text-muted")
+        .child("Loading the event…")
+}
+
+// Read-only, and muted: the words can still change until the block is created
+fn partial_row(block: BlockMessage) -> impl Element {
+    let text = block
+        .words
+        .iter()
+        .map(|word| word.text.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    RawHtmlEl::new("table")
+        .attr("class", "table text-muted")
+        .child(
+            RawHtmlEl::new("tbody").child(
+                RawHtmlEl::new("tr")
+                    .attr("id", "partial-block")
+                    .attr("class", block.speaker.as_str())
+                    .child(block_id(block.id))
+                    .child(
+                        RawHtmlEl::new("td")
+                            .attr("class", "col-md-1")
+                            .child(display_name(&speaker_profiles().lock_ref(), &block.speaker)),
+                    )
+                    .child(
+                        RawHtmlEl::new("td").child(
+                            RawHtmlEl::new("p")
+                                .attr("class", "col-md-8")
+                                .child(format!("{} …", text)),
+                        ),
+                    ),
+            ),
+        )
+}
+
 fn block(block: Arc<RenderBlock>) -> impl Element {
     let id = block.id;
     RawHtmlEl::new("tr")
@@ -334,13 +863,14 @@ fn block(block: Arc<RenderBlock>) -> impl Element {
                 .is_visible
                 .signal_ref(move |is_visible| (!*is_visible).then(|| "hide")),
         )
-        .attr("class", block.speaker.as_str())
+        .attr_signal("class", block.speaker.signal_cloned())
         .child(block_id(id))
         .child(block_speaker(id, block.speaker.clone()))
-        .child(block_text(block))
+        .child(block_text(block.clone()))
         .child(block_edit_button(id))
         .child(block_merge_above(id))
         .child(block_remove_button(id))
+        .child(block_approve_button(block))
         .child(block_play_button(id))
 }
 
@@ -348,12 +878,35 @@ fn block_id(id: BlockId) -> impl Element {
     RawHtmlEl::new("td").attr("class", "col-md-1").child(id)
 }
 
-fn block_speaker(id: BlockId, speaker: String) -> impl Element {
-    RawHtmlEl::new("td").attr("class", "col-md-1").child(
-        RawHtmlEl::new("a")
-            .event_handler(move |_: events::Click| select_block(id))
-            .child(speaker),
-    )
+// The speaker's name, in their color, with their role below it
+fn block_speaker(id: BlockId, speaker: Mutable<String>) -> impl Element {
+    let shown = map_ref! {
+        let label = speaker.signal_cloned(),
+        let profiles = speaker_profiles().signal_cloned() =>
+        (display_name(profiles, label), profiles.get(label).cloned().unwrap_or_default())
+    };
+    RawHtmlEl::new("td")
+        .attr("class", "col-md-1")
+        .child_signal(shown.map(move |(name, profile)| speaker_label(id, name, profile)))
+}
+
+fn speaker_label(id: BlockId, name: String, profile: SpeakerProfile) -> impl Element {
+    let style = profile
+        .color
+        .map(|color| format!("color: {};", color))
+        .unwrap_or_default();
+    RawHtmlEl::new("div")
+        .child(
+            RawHtmlEl::new("a")
+                .attr("style", &style)
+                .event_handler(move |_: events::Click| select_block(id))
+                .child(name),
+        )
+        .children(profile.role.map(|role| {
+            RawHtmlEl::new("div")
+                .attr("class", "text-muted small")
+                .child(role)
+        }))
 }
 
 fn block_text(block: Arc<RenderBlock>) -> impl Element {
@@ -385,48 +938,230 @@ pub fn original_text_as_p(block: &Arc<RenderBlock>, width_class: &str) -> impl E
         }))
 }
 
+// The cell stays for users without the role, so the columns still line up
 fn block_edit_button(id: BlockId) -> impl Element {
-    RawHtmlEl::new("td").attr("class", "col-1").child(
-        RawHtmlEl::new("a")
-            .event_handler(move |_: events::Click| edit_block(id))
-            .child(
-                // TODO: Investigate creating a custom SpanWithTooltip element, there's a lot of boiler plate below
-                RawHtmlEl::new("span")
-                    .attr("class", "glyphicon glyphicon-edit edit")
-                    .attr("aria-hidden", "true")
-                    .attr("data-toggle", "tooltip")
-                    .attr("data-placement", "bottom")
-                    .attr("title", "Edit block contents"),
-            ),
-    )
+    RawHtmlEl::new("td")
+        .attr("class", "col-1")
+        .child_signal(has_role(Role::Editor).map(move |can_edit| {
+            can_edit.then(|| {
+                RawHtmlEl::new("a")
+                    .event_handler(move |_: events::Click| edit_block(id))
+                    .child(
+                        // TODO: Investigate creating a custom SpanWithTooltip element, there's a lot of boiler plate below
+                        RawHtmlEl::new("span")
+                            .attr("class", "glyphicon glyphicon-edit edit")
+                            .attr("aria-hidden", "true")
+                            .attr("data-toggle", "tooltip")
+                            .attr("data-placement", "bottom")
+                            .attr("title", "Edit block contents"),
+                    )
+            })
+        }))
 }
 fn block_merge_above(id: BlockId) -> impl Element {
-    RawHtmlEl::new("td").attr("class", "col-1").child(
-        RawHtmlEl::new("a")
-            .event_handler(move |_: events::Click| merge_above(id))
-            .child(
+    RawHtmlEl::new("td")
+        .attr("class", "col-1")
+        .child_signal(has_role(Role::Editor).map(move |can_edit| {
+            can_edit.then(|| {
+                RawHtmlEl::new("a")
+                    .event_handler(move |_: events::Click| merge_above(id))
+                    .child(
+                        RawHtmlEl::new("span")
+                            .attr("class", "glyphicon glyphicon-upload upload")
+                            .attr("aria-hidden", "true")
+                            .attr("data-toggle", "tooltip")
+                            .attr("data-placement", "bottom")
+                            .attr("title", "Merge with block above"),
+                    )
+            })
+        }))
+}
+
+fn block_remove_button(id: BlockId) -> impl Element {
+    RawHtmlEl::new("td")
+        .attr("class", "col-1")
+        .child_signal(has_role(Role::Editor).map(move |can_edit| {
+            can_edit.then(|| {
+                RawHtmlEl::new("a")
+                    .event_handler(move |_: events::Click| remove_block(id))
+                    .child(
+                        RawHtmlEl::new("span")
+                            .attr("class", "glyphicon glyphicon-remove remove")
+                            .attr("aria-hidden", "true")
+                            .attr("data-toggle", "tooltip")
+                            .attr("data-placement", "bottom")
+                            .attr("title", "Remove this block"),
+                    )
+            })
+        }))
+}
+
+// Everyone sees that a block is approved, only reviewers get to approve it
+fn block_approve_button(block: Arc<RenderBlock>) -> impl Element {
+    let id = block.id;
+    let approved_by = map_ref! {
+        let approval = block.approval.signal_cloned(),
+        let version = block.version.signal() =>
+        approval
+            .as_ref()
+            .filter(|approval| approval.version == *version)
+            .map(|approval| approval.approved_by.clone())
+    };
+    let glyph = map_ref! {
+        let approved_by = approved_by,
+        let can_approve = has_role(Role::Reviewer) =>
+        match (approved_by, can_approve) {
+            (Some(approved_by), _) => Some(
                 RawHtmlEl::new("span")
-                    .attr("class", "glyphicon glyphicon-upload upload")
+                    .attr("class", "glyphicon glyphicon-ok text-success")
                     .attr("aria-hidden", "true")
                     .attr("data-toggle", "tooltip")
                     .attr("data-placement", "bottom")
-                    .attr("title", "Merge with block above"),
+                    .attr("title", &format!("Approved by {}", approved_by)),
             ),
-    )
+            (None, true) => Some(
+                RawHtmlEl::new("a")
+                    .event_handler(move |_: events::Click| approve_block(id))
+                    .child(
+                        RawHtmlEl::new("span")
+                            .attr("class", "glyphicon glyphicon-ok approve")
+                            .attr("aria-hidden", "true")
+                            .attr("data-toggle", "tooltip")
+                            .attr("data-placement", "bottom")
+                            .attr("title", "Approve this block"),
+                    ),
+            ),
+            (None, false) => None,
+        }
+    };
+    RawHtmlEl::new("td")
+        .attr("class", "col-1")
+        .child_signal(glyph)
 }
 
-fn block_remove_button(id: BlockId) -> impl Element {
-    RawHtmlEl::new("td").attr("class", "col-1").child(
-        RawHtmlEl::new("a")
-            .event_handler(move |_: events::Click| remove_block(id))
+// Every label in the event, named or not. Saving a name renames the speaker in every block and
+// export at once.
+fn speakers_panel() -> impl Element {
+    let speakers = map_ref! {
+        let profiles = speaker_profiles().signal_cloned(),
+        let labels = speaker_labels(),
+        let library = speaker_library().signal_cloned() =>
+        labels
+            .iter()
+            .map(|label| SpeakerEntry {
+                label: label.clone(),
+                profile: profiles.get(label).cloned().unwrap_or_default(),
+                suggestions: library
+                    .as_ref()
+                    .map(|library| suggested_people(library, profiles, label))
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<SpeakerEntry>>()
+    };
+    RawHtmlEl::new("div")
+        .attr("class", "row")
+        .child(RawHtmlEl::new("h3").child("Speakers"))
+        .child_signal(speakers.dedupe_cloned().map(|speakers| {
+            RawHtmlEl::new("table")
+                .attr("class", "table table-condensed")
+                .child(RawHtmlEl::new("tbody").children(speakers.into_iter().map(speaker_row)))
+        }))
+}
+
+fn speaker_row(entry: SpeakerEntry) -> impl Element {
+    let SpeakerEntry {
+        label,
+        profile,
+        suggestions,
+    } = entry;
+    let person = profile.person;
+    let name = Mutable::new(profile.name);
+    let role = Mutable::new(profile.role.unwrap_or_default());
+    let color = Mutable::new(profile.color.unwrap_or_default());
+    let save = {
+        let (name, role, color) = (name.clone(), role.clone(), color.clone());
+        let label = label.clone();
+        move || {
+            rename_speaker(
+                label.clone(),
+                SpeakerProfile {
+                    name: name.get_cloned().trim().to_string(),
+                    color: non_empty(color.get_cloned()),
+                    role: non_empty(role.get_cloned()),
+                    person,
+                },
+            )
+        }
+    };
+    RawHtmlEl::new("tr")
+        .child(
+            RawHtmlEl::new("td")
+                .attr("class", "col-md-1")
+                .child(format!("Speaker {}", label)),
+        )
+        .child(profile_input("Name", name.clone()))
+        .child(profile_input("Role, e.g. Chair", role))
+        .child(profile_input("Color, e.g. #1f77b4", color))
+        .child(
+            RawHtmlEl::new("td").child(
+                RawHtmlEl::new("button")
+                    .attr("class", "btn btn-default")
+                    .attr("type", "button")
+                    .event_handler(move |_: events::Click| save())
+                    .child("Save"),
+            ),
+        )
+        .child(library_cell(label, person, name, suggestions))
+}
+
+// A linked label can be unlinked. Otherwise the label can be linked to one of the people
+// suggested for it, or its speaker added to the library.
+fn library_cell(
+    label: String,
+    person: Option<PersonId>,
+    name: Mutable<String>,
+    suggestions: Vec<SuggestedPerson>,
+) -> impl Element {
+    let cell = RawHtmlEl::new("td");
+    if person.is_some() {
+        return cell
             .child(
                 RawHtmlEl::new("span")
-                    .attr("class", "glyphicon glyphicon-remove remove")
-                    .attr("aria-hidden", "true")
-                    .attr("data-toggle", "tooltip")
-                    .attr("data-placement", "bottom")
-                    .attr("title", "Remove this block"),
-            ),
+                    .attr("class", "label label-info")
+                    .child("In the speaker library"),
+            )
+            .child(library_button("Unlink", None, move || {
+                link_speaker(label.clone(), None)
+            }));
+    }
+    cell.children(suggestions.into_iter().map(|suggested| {
+        let label = label.clone();
+        library_button(&suggested.text, suggested.notes.as_deref(), move || {
+            link_speaker(label.clone(), Some(suggested.id))
+        })
+    }))
+    .child(library_button("Add to the library", None, move || {
+        add_person(label.clone(), name.get_cloned())
+    }))
+}
+
+fn library_button(text: &str, title: Option<&str>, on_click: impl Fn() + 'static) -> impl Element {
+    RawHtmlEl::new("button")
+        .attr("class", "btn btn-link btn-xs")
+        .attr("type", "button")
+        .attr("title", title.unwrap_or_default())
+        .event_handler(move |_: events::Click| on_click())
+        .child(text.to_string())
+}
+
+fn profile_input(placeholder: &'static str, value: Mutable<String>) -> impl Element {
+    RawHtmlEl::new("td").child(
+        TextInput::new()
+            .s(Padding::all(4))
+            .label_hidden(placeholder)
+            .placeholder(Placeholder::new(placeholder))
+            .text_signal(value.signal_cloned())
+            .on_change(move |text| value.set(text)),
     )
 }
 
@@ -459,6 +1194,153 @@ fn build_full_text(raw_words: MutableVecLockRef<Word>) -> String {
         .join(" ")
 }
 
+fn render_block(msg: BlockMessage) -> RenderBlock {
+    let raw_words = MutableVec::new_with_values(msg.words);
+    let full_text = build_full_text(raw_words.lock_ref());
+    RenderBlock {
+        id: msg.id,
+        speaker: Mutable::new(msg.speaker),
+        raw_words,
+        full_text: Mutable::new(full_text),
+        is_visible: Mutable::new(true),
+        version: Mutable::new(msg.version),
+        approval: Mutable::new(None),
+    }
+}
+
+// The blocks we already show are updated in place, so the pages showing them keep following them
+fn apply_snapshot(snapshot: EventSnapshot) {
+    println!(
+        "Event {} snapshot at update {}",
+        snapshot.event_id, snapshot.seq
+    );
+    let mut blocks = blocks().lock_mut();
+    let order: Vec<BlockId> = snapshot.blocks.iter().map(|block| block.id).collect();
+    for msg in snapshot.blocks {
+        match blocks.iter().find(|block| block.id == msg.id).cloned() {
+            Some(block) => {
+                block_edit_page::block_changed(msg.id);
+                update_block(&block, msg);
+                block.is_visible.set(true);
+                block.approval.set(None); // Unless the snapshot says otherwise, below
+            }
+            None => blocks.push_cloned(Arc::new(render_block(msg))),
+        }
+    }
+    // Deleted or merged away since we last heard
+    for block in blocks.iter().filter(|block| !order.contains(&block.id)) {
+        block.is_visible.set(false);
+    }
+    reorder_blocks(&mut blocks, &order);
+    for approval in snapshot.approvals {
+        if let Some(block) = blocks.iter().find(|block| block.id == approval.id) {
+            block.approval.set(Some(approval));
+        }
+    }
+    partial_block().set(None);
+    speaker_profiles().set(snapshot.speakers);
+    audio_url().set_neq(Some(snapshot.audio_url));
+    is_event_finished().set(snapshot.is_finished);
+    last_seq().set(Some(snapshot.seq));
+    is_event_loaded().set_neq(true);
+    load_audio();
+}
+
+// Take on the block as the backend holds it now
+fn update_block(block: &RenderBlock, msg: BlockMessage) {
+    block.speaker.set(msg.speaker);
+    block.raw_words.lock_mut().replace_cloned(msg.words);
+    block
+        .full_text
+        .set(build_full_text(block.raw_words.lock_ref()));
+    block.version.set(msg.version);
+}
+
+// Put the blocks in the backend's order; hidden (merged) blocks aren't in it, they go last
+fn reorder_blocks(blocks: &mut MutableVecLockMut<Arc<RenderBlock>>, order: &[BlockId]) {
+    let mut ordered = blocks.to_vec();
+    ordered.sort_by_key(|block| {
+        order
+            .iter()
+            .position(|id| *id == block.id)
+            .unwrap_or(usize::MAX)
+    });
+    blocks.replace_cloned(ordered);
+}
+
+// The people other events linked a speaker of the label's name to first, then the most used,
+// leaving out anyone already linked to a label in this event
+fn suggested_people(
+    library: &SpeakerLibrary,
+    profiles: &SpeakerProfiles,
+    label: &str,
+) -> Vec<SuggestedPerson> {
+    let linked: BTreeSet<PersonId> = profiles
+        .values()
+        .filter_map(|profile| profile.person)
+        .collect();
+    let name = profiles
+        .get(label)
+        .map(|profile| name_key(&profile.name))
+        .unwrap_or_default();
+    let by_name = library
+        .suggestions
+        .get(&name)
+        .into_iter()
+        .flatten()
+        .filter_map(|suggestion| {
+            let known = library
+                .people
+                .iter()
+                .find(|known| known.person.id == suggestion.person_id)?;
+            let text = match suggestion.times {
+                0 => format!("{} (same name)", known.person.name),
+                times => format!("{} (this name in {} events)", known.person.name, times),
+            };
+            Some((&known.person, text))
+        });
+    let by_use = library.people.iter().map(|known| {
+        let text = format!("{} ({} events)", known.person.name, known.events);
+        (&known.person, text)
+    });
+    let mut suggested: Vec<SuggestedPerson> = Vec::new();
+    for (person, text) in by_name.chain(by_use) {
+        if linked.contains(&person.id) || suggested.iter().any(|other| other.id == person.id) {
+            continue;
+        }
+        suggested.push(SuggestedPerson {
+            id: person.id,
+            text,
+            notes: person.notes.clone(),
+        });
+    }
+    suggested.truncate(MAX_SUGGESTIONS);
+    suggested
+}
+
+fn non_empty(text: String) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn is_typing(event: &web_sys::KeyboardEvent) -> bool {
+    event.target().map_or(false, |target| {
+        target.dyn_ref::<web_sys::HtmlInputElement>().is_some()
+    })
+}
+
+fn alert(message: &str) {
+    if let Some(window) = web_sys::window() {
+        let _ = window.alert_with_message(message);
+    }
+}
+
+#[wasm_bindgen(module = "/js/download.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = downloadExport)]
+    fn download_export(url: &str, token: &str, on_done: &Closure<dyn FnMut(u16)>);
+}
+
 #[wasm_bindgen(module = "/js/audio-player.js")]
 extern "C" {
     #[wasm_bindgen(js_name = loadAudio)]
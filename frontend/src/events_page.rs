@@ -1,15 +1,307 @@
+use crate::app;
+use crate::event_edit_page::connection;
 use crate::router::Route;
-use zoon::{named_color::*, *};
+use shared::roles::Role;
+use shared::{
+    AccountSummary, EventChoiceMessage, EventId, EventStatus, EventSummary, RoleAssignment, UpMsg,
+};
+use zoon::{eprintln, named_color::*, *};
+
+const AUDIO_INPUT_ID: &str = "new-event-audio";
+
+// ------ ------
+//    States
+// ------ ------
+
+#[static_ref]
+fn events() -> &'static MutableVec<EventSummary> {
+    MutableVec::new()
+}
+
+#[static_ref]
+fn new_event_title() -> &'static Mutable<String> {
+    Mutable::new(String::new())
+}
+
+#[static_ref]
+fn upload_progress() -> &'static Mutable<Option<f64>> {
+    Mutable::new(None)
+}
+
+#[static_ref]
+fn upload_error() -> &'static Mutable<Option<String>> {
+    Mutable::new(None)
+}
+
+// Only filled in for admins
+#[static_ref]
+fn accounts() -> &'static MutableVec<AccountSummary> {
+    MutableVec::new()
+}
+
+// ------ ------
+//   Commands
+// ------ ------
+
+pub fn set_events(new_events: Vec<EventSummary>) {
+    events().lock_mut().replace_cloned(new_events);
+}
+
+pub fn set_accounts(new_accounts: Vec<AccountSummary>) {
+    accounts().lock_mut().replace_cloned(new_accounts);
+}
+
+fn request_events() {
+    Task::start(async {
+        let result = connection().send_up_msg(UpMsg::ListEvents).await;
+        if let Err(error) = result {
+            eprintln!("Failed to send list events message: {:?}.", error);
+        }
+    });
+}
+
+fn request_accounts() {
+    Task::start(async {
+        let result = connection().send_up_msg(UpMsg::ListAccounts).await;
+        if let Err(error) = result {
+            eprintln!("Failed to send list accounts message: {:?}.", error);
+        }
+    });
+}
+
+// The backend answers with every account as it stands after the change
+fn set_role(username: String, role: Role) {
+    Task::start(async move {
+        let assignment = RoleAssignment {
+            username,
+            event_id: None,
+            role: Some(role),
+        };
+        let result = connection().send_up_msg(UpMsg::SetRole(assignment)).await;
+        if let Err(error) = result {
+            eprintln!("Failed to send set role message: {:?}.", error);
+        }
+    });
+}
+
+// Once the admin has said they're sure, there's no getting it back
+fn delete_event(id: EventId, title: &str) {
+    let question = format!(
+        "Delete \"{}\" with its audio, transcript, corrections and history? This can't be undone.",
+        title
+    );
+    let is_sure = web_sys::window().map_or(false, |window| {
+        window.confirm_with_message(&question).unwrap_or(false)
+    });
+    if !is_sure {
+        return;
+    }
+    Task::start(async move {
+        let result = connection()
+            .send_up_msg(UpMsg::DeleteEvent(EventChoiceMessage { id }))
+            .await;
+        if let Err(error) = result {
+            eprintln!("Failed to send delete event message: {:?}.", error);
+        }
+    });
+}
+
+fn set_new_event_title(title: String) {
+    new_event_title().set(title);
+}
+
+fn upload_new_event() {
+    let title = new_event_title().get_cloned();
+    if title.trim().is_empty() {
+        upload_error().set(Some("Give the event a title first".to_string()));
+        return;
+    }
+    let token = match app::login_token() {
+        Some(token) => token,
+        None => {
+            upload_error().set(Some("Log in to create an event".to_string()));
+            return;
+        }
+    };
+    upload_error().take();
+    upload_progress().set(Some(0.0));
+
+    let on_progress = Closure::wrap(Box::new(|percent: f64| {
+        upload_progress().set(Some(percent));
+    }) as Box<dyn FnMut(f64)>);
+    let on_done = Closure::wrap(Box::new(|status: u16| {
+        upload_progress().take();
+        match status {
+            200 => {
+                new_event_title().take();
+                request_events();
+            }
+            413 => upload_error().set(Some("The recording is too big to upload".to_string())),
+            _ => upload_error().set(Some(
+                "Upload failed, are you (still) logged in as an admin?".to_string(),
+            )),
+        }
+    }) as Box<dyn FnMut(u16)>);
+    upload_event_audio(AUDIO_INPUT_ID, &title, &token, &on_progress, &on_done);
+    // JS calls these long after we've returned, hand them over for good
+    on_progress.forget();
+    on_done.forget();
+}
 
 // ------ ------
 //     View
 // ------ ------
 
+// Only admins create events and manage users
 pub fn page() -> impl Element {
+    request_events();
+    if app::user_role().get() == Some(Role::Admin) {
+        request_accounts();
+    }
     Column::new()
         .s(Spacing::new(20))
-        .item(link("Gettysburg Address", Route::Event { event_id: 1 }))
-    // .item(link("TBD", Route::Event{event_id: 2}))
+        .item_signal(app::is_admin().map(|is_admin| is_admin.then(new_event_form)))
+        .item(
+            Column::new()
+                .s(Spacing::new(20))
+                .items_signal_vec(events().signal_vec_cloned().map(event_row)),
+        )
+        .item_signal(app::is_admin().map(|is_admin| is_admin.then(accounts_panel)))
+}
+
+fn new_event_form() -> impl Element {
+    Row::new()
+        .s(Spacing::new(10))
+        .item(title_input())
+        .item(
+            RawHtmlEl::new("input")
+                .attr("id", AUDIO_INPUT_ID)
+                .attr("type", "file")
+                .attr("accept", "audio/*"),
+        )
+        .item(upload_button())
+        .item_signal(
+            upload_progress()
+                .signal()
+                .map(|percent| percent.map(progress_bar)),
+        )
+        .item_signal(upload_error().signal_cloned().map(|error| {
+            error.map(|error| {
+                RawHtmlEl::new("span")
+                    .attr("class", "label label-danger")
+                    .child(error)
+            })
+        }))
+}
+
+fn title_input() -> impl Element {
+    TextInput::new()
+        .s(Padding::all(7))
+        .label_hidden("New event title")
+        .placeholder(Placeholder::new("New event title"))
+        .text_signal(new_event_title().signal_cloned())
+        .on_change(set_new_event_title)
+}
+
+fn upload_button() -> impl Element {
+    let (hovered, hovered_signal) = Mutable::new_and_signal(false);
+    Button::new()
+        .s(Background::new().color_signal(hovered_signal.map_bool(|| BLUE_2, || BLUE_4)))
+        .s(Padding::all(7))
+        .s(Font::new().color(hsluv!(0, 0, 100)))
+        .s(RoundedCorners::all(5))
+        .on_hovered_change(move |is_hovered| hovered.set(is_hovered))
+        .label("New event")
+        .on_press(upload_new_event)
+}
+
+fn progress_bar(percent: f64) -> impl Element {
+    RawHtmlEl::new("div")
+        .attr("class", "progress col-md-2")
+        .child(
+            RawHtmlEl::new("div")
+                .attr("class", "progress-bar")
+                .attr("role", "progressbar")
+                .attr("style", format!("width: {:.0}%;", percent).as_str())
+                .child(format!("{:.0}%", percent)),
+        )
+}
+
+fn event_row(event: EventSummary) -> impl Element {
+    let (id, title) = (event.id, event.title.clone());
+    Row::new()
+        .s(Spacing::new(10))
+        .item(link(&event.title, Route::Event { event_id: event.id }))
+        .item(event.date.clone())
+        .item(event.language.clone())
+        .item(status_badge(event.status))
+        .item_signal(
+            app::is_admin()
+                .map(move |is_admin| is_admin.then(|| delete_event_button(id, title.clone()))),
+        )
+}
+
+fn delete_event_button(id: EventId, title: String) -> impl Element {
+    RawHtmlEl::new("button")
+        .attr("class", "btn btn-danger btn-xs")
+        .attr("type", "button")
+        .event_handler(move |_: events::Click| delete_event(id, &title))
+        .child("Delete")
+}
+
+fn status_badge(status: EventStatus) -> impl Element {
+    let (class, label) = match status {
+        EventStatus::Pending => ("label label-default", "Pending"),
+        EventStatus::Transcribing => ("label label-info", "Transcribing"),
+        EventStatus::Live => ("label label-primary", "Live"),
+        EventStatus::Finished => ("label label-success", "Finished"),
+        EventStatus::Error => ("label label-danger", "Error"),
+    };
+    RawHtmlEl::new("span").attr("class", class).child(label)
+}
+
+fn accounts_panel() -> impl Element {
+    Column::new()
+        .s(Spacing::new(10))
+        .item(RawHtmlEl::new("h3").child("Users"))
+        .items_signal_vec(accounts().signal_vec_cloned().map(account_row))
+}
+
+// Roles in single events are set with `research set-role`, here they're only listed
+fn account_row(account: AccountSummary) -> impl Element {
+    let event_roles = account
+        .event_roles
+        .iter()
+        .map(|(event_id, role)| format!("event {}: {}", event_id, role.name()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    Row::new()
+        .s(Spacing::new(10))
+        .item(account.username.clone())
+        .item(
+            RawHtmlEl::new("div")
+                .attr("class", "btn-group btn-group-xs")
+                .children(
+                    Role::ALL
+                        .into_iter()
+                        .map(|role| role_button(&account.username, role, role == account.role)),
+                ),
+        )
+        .item(event_roles)
+}
+
+fn role_button(username: &str, role: Role, is_current: bool) -> impl Element {
+    let username = username.to_string();
+    let class = if is_current {
+        "btn btn-primary"
+    } else {
+        "btn btn-default"
+    };
+    RawHtmlEl::new("button")
+        .attr("class", class)
+        .attr("type", "button")
+        .event_handler(move |_: events::Click| set_role(username.clone(), role))
+        .child(role.name())
 }
 
 // TODO! duplicated in header page, move somewhere more useful (app?)
@@ -19,3 +311,15 @@ fn link(label: &str, route: Route) -> impl Element {
         .label(label)
         .to(route)
 }
+
+#[wasm_bindgen(module = "/js/upload.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = uploadEventAudio)]
+    fn upload_event_audio(
+        input_id: &str,
+        title: &str,
+        token: &str,
+        on_progress: &Closure<dyn FnMut(f64)>,
+        on_done: &Closure<dyn FnMut(u16)>,
+    );
+}
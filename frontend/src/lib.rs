@@ -14,6 +14,7 @@ mod router;
 
 #[wasm_bindgen(start)]
 pub fn start() {
+    app::load_login();
     router::router();
     start_app("main", app::root);
     event_edit_page::connection();
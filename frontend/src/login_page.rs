@@ -1,5 +1,6 @@
-use crate::app;
-use zoon::{named_color::*, *};
+use crate::event_edit_page::connection;
+use shared::{LoginMessage, UpMsg};
+use zoon::{eprintln, named_color::*, *};
 
 // ------ ------
 //    States
@@ -10,6 +11,17 @@ fn name() -> &'static Mutable<String> {
     Mutable::new("".to_owned())
 }
 
+#[static_ref]
+fn password() -> &'static Mutable<String> {
+    Mutable::new("".to_owned())
+}
+
+// Why the backend turned the last attempt down
+#[static_ref]
+fn error() -> &'static Mutable<Option<String>> {
+    Mutable::new(None)
+}
+
 // ------ ------
 //   Commands
 // ------ ------
@@ -18,8 +30,26 @@ fn set_name(new_name: String) {
     name().set(new_name);
 }
 
+fn set_password(new_password: String) {
+    password().set(new_password);
+}
+
+pub fn set_error(reason: String) {
+    error().set(Some(reason));
+}
+
+// The backend answers with LoggedIn (see `app::log_in`) or LoginRejected
 fn log_in() {
-    app::log_in(name().get_cloned());
+    error().take();
+    let login = LoginMessage {
+        username: name().get_cloned(),
+        password: password().take(),
+    };
+    Task::start(async move {
+        if let Err(error) = connection().send_up_msg(UpMsg::Login(login)).await {
+            eprintln!("Failed to send login message: {:?}", error);
+        }
+    });
 }
 
 // ------ ------
@@ -27,18 +57,45 @@ fn log_in() {
 // ------ ------
 
 pub fn page() -> impl Element {
-    Row::new().item(name_input()).item(log_in_button())
+    Column::new()
+        .s(Spacing::new(10))
+        .item(
+            Row::new()
+                .s(Spacing::new(10))
+                .item(name_input())
+                .item(password_input())
+                .item(log_in_button()),
+        )
+        .item_signal(error().signal_cloned().map(|error| {
+            error.map(|error| {
+                RawHtmlEl::new("div")
+                    .attr("class", "alert alert-danger")
+                    .attr("role", "alert")
+                    .child(error)
+            })
+        }))
 }
 
 fn name_input() -> impl Element {
     TextInput::new()
         .s(Padding::all(7))
         .label_hidden("Name")
-        .placeholder(Placeholder::new("Username <any will do>"))
+        .placeholder(Placeholder::new("Username"))
         .text(name().get_cloned())
         .on_change(set_name)
 }
 
+fn password_input() -> impl Element {
+    TextInput::new()
+        .s(Padding::all(7))
+        .label_hidden("Password")
+        .placeholder(Placeholder::new("Password"))
+        .input_type(InputType::password())
+        .text_signal(password().signal_cloned())
+        .on_change(set_password)
+        .on_key_down_event(|event| event.if_key(Key::Enter, log_in))
+}
+
 fn log_in_button() -> impl Element {
     let (hovered, hovered_signal) = Mutable::new_and_signal(false);
     Button::new()
@@ -55,14 +55,16 @@ pub fn router() -> &'static Router<Route> {
                 if not(app::is_user_logged()) {
                     return router().replace(Route::Login);
                 }
+                event_edit_page::open_event(event_id);
                 app::set_page_id(PageId::Event);
-                event_edit_page::set_event_id(event_id);
             }
             Route::BlockEdit { event_id, block_id } => {
                 println!("Block edit route");
                 if not(app::is_user_logged()) {
                     return router().replace(Route::Login);
                 }
+                // The block comes with its event's snapshot, after a reload too
+                event_edit_page::open_event(event_id);
                 app::set_page_id(PageId::BlockEdit{event_id, block_id});
                 println!("Routing to block_edit/{}/{}", event_id, block_id);
             }
@@ -1,11 +1,19 @@
+use futures_util::SinkExt;
 use hyper::header;
 use reqwest::Result;
 use serde::{Deserialize, Serialize};
+use shared::accounts::{self, load_accounts, save_accounts, set_password};
+use shared::roles::Role;
+use shared::split::{split_transcript, write_block_files, SplitOptions};
+use shared::transcription::provider_by_name;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::path::Path;
 use std::{env, fs};
+use tokio::net::TcpListener;
 use tokio::time::{sleep, Duration};
+use tokio_tungstenite::tungstenite::Message;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,6 +23,22 @@ async fn main() -> Result<()> {
     if args.len() < 2 {
         panic!("Need to pass in a filename.");
     }
+    if args[1] == "split" {
+        split(&args[2..]);
+        return Ok(());
+    }
+    if args[1] == "replay" {
+        replay(&args[2..]).await;
+        return Ok(());
+    }
+    if args[1] == "add-user" {
+        add_user(&args[2..]);
+        return Ok(());
+    }
+    if args[1] == "set-role" {
+        set_role(&args[2..]);
+        return Ok(());
+    }
 
     let filename = &args[1];
     let mut f = File::open(filename).expect("Problem opening sound file.");
@@ -94,6 +118,146 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+// ////////////////////////////////////////////////////////////////////////////////////////////
+// research split <transcript.json> <event_dir> [--format assembly_ai|aws_transcribe|whisper]
+//     [--max-duration-ms 30000] [--max-words 60] [--first-block 1]
+//
+// Turns one of the transcripts above (or another provider's) into the block files the backend
+// ingests.
+fn split(args: &[String]) {
+    if args.len() < 2 {
+        panic!("Need to pass in a transcript and an event directory.");
+    }
+    let (transcript_file, event_dir) = (&args[0], Path::new(&args[1]));
+
+    let mut format = "assembly_ai".to_string();
+    let mut options = SplitOptions::default();
+    let mut first_block = 1;
+    for option in args[2..].chunks(2) {
+        let value = option.get(1).expect("Option is missing its value.");
+        match option[0].as_str() {
+            "--format" => format = value.clone(),
+            "--max-duration-ms" => options.max_duration_ms = value.parse().expect("Bad duration."),
+            "--max-words" => options.max_words = value.parse().expect("Bad word count."),
+            "--first-block" => first_block = value.parse().expect("Bad block number."),
+            other => panic!("Unknown option {}", other),
+        }
+    }
+
+    let provider = provider_by_name(&format).expect("Unknown transcript format.");
+    let raw = fs::read_to_string(transcript_file).expect("Problem reading transcript.");
+    let utterances = provider
+        .normalize(&raw)
+        .expect("Problem normalizing transcript.");
+    let blocks = split_transcript(utterances, &options);
+    let count =
+        write_block_files(event_dir, &blocks, first_block).expect("Problem writing blocks.");
+    println!("Done! {} blocks in {}", count, event_dir.display());
+}
+
+// ////////////////////////////////////////////////////////////////////////////////////////////
+// research replay <session.jsonl> [--port 8765] [--delay-ms 500]
+//
+// A stand-in for the real-time API: plays a session recorded by `realtime` to whoever connects,
+// one message per delay, then closes. Point an event's `realtime_url` at ws://127.0.0.1:<port>.
+async fn replay(args: &[String]) {
+    if args.is_empty() {
+        panic!("Need to pass in a recorded session.");
+    }
+    let session = fs::read_to_string(&args[0]).expect("Problem reading session.");
+
+    let mut port: u16 = 8765;
+    let mut delay = Duration::from_millis(500);
+    for option in args[1..].chunks(2) {
+        let value = option.get(1).expect("Option is missing its value.");
+        match option[0].as_str() {
+            "--port" => port = value.parse().expect("Bad port."),
+            "--delay-ms" => delay = Duration::from_millis(value.parse().expect("Bad delay.")),
+            other => panic!("Unknown option {}", other),
+        }
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .expect("Problem binding port.");
+    println!("Replaying {} on ws://127.0.0.1:{}", args[0], port);
+    loop {
+        let (stream, peer) = listener
+            .accept()
+            .await
+            .expect("Problem accepting connection.");
+        let session = session.clone();
+        tokio::spawn(async move {
+            let mut socket = match tokio_tungstenite::accept_async(stream).await {
+                Ok(socket) => socket,
+                Err(err) => return eprintln!("Not a websocket ({}): {:?}", peer, err),
+            };
+            println!("Replaying to {}", peer);
+            for line in session.lines().filter(|line| !line.trim().is_empty()) {
+                sleep(delay).await;
+                if socket.send(Message::Text(line.to_string())).await.is_err() {
+                    return println!("{} went away", peer);
+                }
+            }
+            let _ = socket.close(None).await;
+            println!("Done replaying to {}", peer);
+        });
+    }
+}
+
+// ////////////////////////////////////////////////////////////////////////////////////////////
+// research add-user <accounts.json> <username>
+//
+// Adds a viewer account for the backend (or resets its password). The password is read from
+// stdin, so it stays out of the shell history.
+fn add_user(args: &[String]) {
+    if args.len() < 2 {
+        panic!("Need to pass in an accounts file and a username.");
+    }
+    let (accounts_file, username) = (Path::new(&args[0]), &args[1]);
+
+    println!("Password for {}:", username);
+    let mut password = String::new();
+    io::stdin()
+        .read_line(&mut password)
+        .expect("Problem reading password.");
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.is_empty() {
+        panic!("Need a password.");
+    }
+
+    let mut accounts = load_accounts(accounts_file).expect("Problem reading accounts.");
+    set_password(&mut accounts, username, password);
+    save_accounts(accounts_file, &accounts).expect("Problem writing accounts.");
+    println!("Done! {} can log in", username);
+}
+
+// ////////////////////////////////////////////////////////////////////////////////////////////
+// research set-role <accounts.json> <username> <viewer|editor|reviewer|admin|none> [event id]
+//
+// Without an event id, the user's role in every event. With one, their role in just that event;
+// `none` there drops them back to their role in every event. Someone has to be the first admin.
+fn set_role(args: &[String]) {
+    if args.len() < 3 {
+        panic!("Need to pass in an accounts file, a username and a role.");
+    }
+    let (accounts_file, username) = (Path::new(&args[0]), &args[1]);
+    let role = match args[2].as_str() {
+        "none" => None,
+        name => Some(Role::from_name(name).expect("Unknown role.")),
+    };
+    let event_id = args
+        .get(3)
+        .map(|event_id| event_id.parse().expect("Event id should be a number."));
+
+    let mut accounts = load_accounts(accounts_file).expect("Problem reading accounts.");
+    if !accounts::set_role(&mut accounts, username, event_id, role) {
+        panic!("No account for {}.", username);
+    }
+    save_accounts(accounts_file, &accounts).expect("Problem writing accounts.");
+    println!("Done! {} is now {}", username, args[2]);
+}
+
 // ////////////////////////////////////////////////////////////////////////////////////////////
 // Types AAI data structures (used in deserialize calls)
 
@@ -0,0 +1,127 @@
+use crate::roles::Role;
+use crate::EventId;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use moonlight::{serde, serde_json, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+// ------ ------
+//     Types
+// ------ ------
+
+// Someone who can log in. Only the Argon2 hash of the password is kept, as a PHC string (salt and
+// parameters included). `role` holds in every event, unless `event_roles` says otherwise for one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct Account {
+    pub username: String,
+    pub password_hash: String,
+    #[serde(default = "legacy_role")] // Accounts from before roles could edit
+    pub role: Role,
+    #[serde(default)]
+    pub event_roles: BTreeMap<EventId, Role>,
+}
+
+impl Account {
+    // Outside any event (the events page) only the account-wide role counts
+    pub fn role_for(&self, event_id: Option<EventId>) -> Role {
+        event_id
+            .and_then(|event_id| self.event_roles.get(&event_id).copied())
+            .unwrap_or(self.role)
+    }
+}
+
+// New accounts only look until an admin gives them more
+fn default_role() -> Role {
+    Role::Viewer
+}
+
+fn legacy_role() -> Role {
+    Role::Editor
+}
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// No accounts file yet is simply no accounts
+pub fn load_accounts(path: &Path) -> io::Result<Vec<Account>> {
+    match File::open(path) {
+        Ok(file) => Ok(serde_json::from_reader(file)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_accounts(path: &Path, accounts: &[Account]) -> io::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    serde_json::to_writer_pretty(File::create(&tmp_path)?, accounts)?;
+    fs::rename(tmp_path, path)
+}
+
+// Adds the account, or gives an existing one a new password
+pub fn set_password(accounts: &mut Vec<Account>, username: &str, password: &str) {
+    let password_hash = hash_password(password);
+    match accounts
+        .iter_mut()
+        .find(|account| account.username == username)
+    {
+        Some(account) => account.password_hash = password_hash,
+        None => accounts.push(Account {
+            username: username.to_string(),
+            password_hash,
+            role: default_role(),
+            event_roles: BTreeMap::new(),
+        }),
+    }
+}
+
+// The account-wide role with no `event_id`, otherwise the role in that event. No role for an
+// event drops back to the account-wide one. False if there's no such account.
+pub fn set_role(
+    accounts: &mut [Account],
+    username: &str,
+    event_id: Option<EventId>,
+    role: Option<Role>,
+) -> bool {
+    let account = match accounts
+        .iter_mut()
+        .find(|account| account.username == username)
+    {
+        Some(account) => account,
+        None => return false,
+    };
+    match (event_id, role) {
+        (None, Some(role)) => account.role = role,
+        (None, None) => account.role = default_role(),
+        (Some(event_id), Some(role)) => {
+            account.event_roles.insert(event_id, role);
+        }
+        (Some(event_id), None) => {
+            account.event_roles.remove(&event_id);
+        }
+    }
+    true
+}
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Argon2 with its default parameters hashes any password")
+        .to_string()
+}
+
+// A hash we can't parse never matches
+pub fn verify_password(account: &Account, password: &str) -> bool {
+    match PasswordHash::new(&account.password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
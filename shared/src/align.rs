@@ -0,0 +1,306 @@
+use crate::Word;
+
+// ------ ------
+//     Types
+// ------ ------
+
+// How one original word or corrected token fares in the cheapest edit from one text to the other
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AlignStep {
+    // (original word, token): the same word, give or take case and punctuation
+    Keep(usize, usize),
+    // (original word, token): a different word said in the same place
+    Substitute(usize, usize),
+    // A token with no original word
+    Insert(usize),
+    // An original word with no token
+    Delete(usize),
+}
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Turns a block's corrected text back into timed words. Kept and substituted words take the
+// timings of the original word they line up with; runs of inserted words share out the gap
+// between their aligned neighbours. Deleted words simply drop out.
+pub fn align_words(original: &[Word], corrected_text: &str) -> Vec<Word> {
+    let block_start = original.first().map_or(0, |word| word.start);
+    let block_end = original.last().map_or(block_start, |word| word.end);
+    align_words_within(original, corrected_text, block_start, block_end)
+}
+
+// As `align_words`, with inserted words at either end allowed out to `window_start`/`window_end`
+// (the neighbouring words, when `original` is only part of a block)
+pub(crate) fn align_words_within(
+    original: &[Word],
+    corrected_text: &str,
+    window_start: usize,
+    window_end: usize,
+) -> Vec<Word> {
+    let tokens: Vec<&str> = corrected_text.split_whitespace().collect();
+    let mut aligned: Vec<Option<Word>> = vec![None; tokens.len()];
+    for step in align_steps(original, &tokens) {
+        match step {
+            AlignStep::Keep(i, j) => {
+                aligned[j] = Some(Word {
+                    text: tokens[j].to_string(),
+                    ..original[i].clone()
+                })
+            }
+            // A human typed it, that's as confident as we get
+            AlignStep::Substitute(i, j) => {
+                aligned[j] = Some(Word {
+                    confidence: 1.0,
+                    text: tokens[j].to_string(),
+                    ..original[i].clone()
+                })
+            }
+            AlignStep::Insert(_) | AlignStep::Delete(_) => {}
+        }
+    }
+
+    let mut words: Vec<Word> = Vec::with_capacity(tokens.len());
+    let mut j = 0;
+    while j < tokens.len() {
+        if let Some(word) = aligned[j].take() {
+            words.push(word);
+            j += 1;
+            continue;
+        }
+        let run_end = (j..tokens.len())
+            .find(|&k| aligned[k].is_some())
+            .unwrap_or(tokens.len());
+        let next = aligned.get(run_end).and_then(Option::as_ref);
+        let gap_start = words.last().map_or(window_start, |word| word.end);
+        let gap_end = next.map_or(window_end, |word| word.start).max(gap_start);
+        let speaker = words
+            .last()
+            .or(next)
+            .or_else(|| original.first())
+            .and_then(|word| word.speaker.clone());
+        words.extend(spread_words(
+            &tokens[j..run_end],
+            gap_start,
+            gap_end,
+            speaker,
+        ));
+        j = run_end;
+    }
+    words
+}
+
+// Levenshtein over words, in order. Ties go to keeping/substituting so as many tokens as
+// possible end up with real timings.
+pub fn align_steps(original: &[Word], tokens: &[&str]) -> Vec<AlignStep> {
+    let (n, m) = (original.len(), tokens.len());
+    // costs[i][j]: the fewest edits turning the first i original words into the first j tokens
+    let mut costs = vec![vec![0usize; m + 1]; n + 1];
+    for (i, row) in costs.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cost) in costs[0].iter_mut().enumerate() {
+        *cost = j;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let substitution = usize::from(!same_word(&original[i - 1].text, tokens[j - 1]));
+            costs[i][j] = (costs[i - 1][j - 1] + substitution)
+                .min(costs[i - 1][j] + 1)
+                .min(costs[i][j - 1] + 1);
+        }
+    }
+
+    let mut steps = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 {
+            let same = same_word(&original[i - 1].text, tokens[j - 1]);
+            if costs[i][j] == costs[i - 1][j - 1] + usize::from(!same) {
+                steps.push(if same {
+                    AlignStep::Keep(i - 1, j - 1)
+                } else {
+                    AlignStep::Substitute(i - 1, j - 1)
+                });
+                i -= 1;
+                j -= 1;
+                continue;
+            }
+        }
+        if j > 0 && (i == 0 || costs[i][j] == costs[i][j - 1] + 1) {
+            steps.push(AlignStep::Insert(j - 1));
+            j -= 1;
+        } else {
+            steps.push(AlignStep::Delete(i - 1));
+            i -= 1;
+        }
+    }
+    steps.reverse();
+    steps
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// Words nobody timed (typed in by a human) share out the time between `start` and `end` evenly
+pub(crate) fn spread_words(
+    texts: &[&str],
+    start: usize,
+    end: usize,
+    speaker: Option<String>,
+) -> Vec<Word> {
+    let end = end.max(start);
+    let step = (end - start) / texts.len().max(1);
+    texts
+        .iter()
+        .enumerate()
+        .map(|(idx, text)| Word {
+            confidence: 1.0, // A human typed it, that's as confident as we get
+            end: if idx + 1 == texts.len() {
+                end
+            } else {
+                start + step * (idx + 1)
+            },
+            speaker: speaker.clone(),
+            start: start + step * idx,
+            text: text.to_string(),
+        })
+        .collect()
+}
+
+// "Hello," and "hello" are the same word said at the same time, only the text changes
+fn same_word(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+fn normalize(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A word a second, each 800ms long, all from speaker A, transcribed at 0.5 confidence
+    fn words(text: &str) -> Vec<Word> {
+        text.split_whitespace()
+            .enumerate()
+            .map(|(idx, text)| Word {
+                confidence: 0.5,
+                start: idx * 1000,
+                end: idx * 1000 + 800,
+                speaker: Some("A".to_string()),
+                text: text.to_string(),
+            })
+            .collect()
+    }
+
+    fn timings(words: &[Word]) -> Vec<(&str, usize, usize)> {
+        words
+            .iter()
+            .map(|word| (word.text.as_str(), word.start, word.end))
+            .collect()
+    }
+
+    #[test]
+    fn steps_keep_substitute_insert_and_delete() {
+        use AlignStep::*;
+        let original = words("so the cat sat down");
+        let steps = align_steps(&original, &["The", "cat", "sat", "there"]);
+        assert_eq!(
+            steps,
+            vec![
+                Delete(0),
+                Keep(1, 0),
+                Keep(2, 1),
+                Keep(3, 2),
+                Substitute(4, 3)
+            ]
+        );
+        let steps = align_steps(&words("the cat sat"), &["the", "fat", "cat", "sat"]);
+        assert_eq!(steps, vec![Keep(0, 0), Insert(1), Keep(1, 2), Keep(2, 3)]);
+    }
+
+    #[test]
+    fn words_differing_in_case_or_punctuation_are_kept() {
+        assert!(same_word("Hello,", "hello"));
+        assert!(same_word("\"okay?\"", "Okay"));
+        assert!(!same_word("hello", "hullo"));
+    }
+
+    #[test]
+    fn kept_and_substituted_words_take_the_original_timings() {
+        let aligned = align_words(&words("the cat sat"), "The dog sat.");
+        assert_eq!(
+            timings(&aligned),
+            vec![("The", 0, 800), ("dog", 1000, 1800), ("sat.", 2000, 2800)]
+        );
+        assert_eq!(aligned[0].confidence, 0.5);
+        assert_eq!(aligned[1].confidence, 1.0);
+    }
+
+    #[test]
+    fn inserted_words_share_out_the_gap() {
+        let aligned = align_words(&words("one two"), "one and a two");
+        assert_eq!(
+            timings(&aligned),
+            vec![
+                ("one", 0, 800),
+                ("and", 800, 900),
+                ("a", 900, 1000),
+                ("two", 1000, 1800)
+            ]
+        );
+        assert!(aligned
+            .iter()
+            .all(|word| word.speaker.as_deref() == Some("A")));
+    }
+
+    #[test]
+    fn inserted_words_at_the_ends_stay_inside_the_block() {
+        let aligned = align_words(&words("one two"), "so one two three");
+        assert_eq!(
+            timings(&aligned),
+            vec![
+                ("so", 0, 0),
+                ("one", 0, 800),
+                ("two", 1000, 1800),
+                ("three", 1800, 1800)
+            ]
+        );
+        let widened = align_words_within(&words("one two"), "so one two three", 0, 2500);
+        assert_eq!(widened[3].end, 2500);
+    }
+
+    #[test]
+    fn deleted_words_drop_out() {
+        let aligned = align_words(&words("um one uh two"), "one two");
+        assert_eq!(
+            timings(&aligned),
+            vec![("one", 1000, 1800), ("two", 3000, 3800)]
+        );
+    }
+
+    #[test]
+    fn a_rewritten_block_stays_inside_its_span() {
+        assert!(align_words(&words("a b c"), "").is_empty());
+        let aligned = align_words(&words("a b c"), "x y");
+        assert_eq!(aligned.len(), 2);
+        assert!(aligned
+            .iter()
+            .all(|word| word.start <= word.end && word.end <= 2800));
+    }
+
+    #[test]
+    fn spread_words_ends_on_the_gap_end() {
+        let spread = spread_words(&["a", "b", "c"], 100, 200, None);
+        assert_eq!(
+            timings(&spread),
+            vec![("a", 100, 133), ("b", 133, 166), ("c", 166, 200)]
+        );
+        let squeezed = spread_words(&["a", "b"], 300, 200, None);
+        assert_eq!(timings(&squeezed), vec![("a", 300, 300), ("b", 300, 300)]);
+    }
+}
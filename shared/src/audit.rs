@@ -0,0 +1,78 @@
+use crate::{BlockId, EventId};
+use moonlight::{serde, Deserialize, Serialize};
+
+// ------ ------
+//     Types
+// ------ ------
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub enum AuditAction {
+    Edit,
+    SpeakerChange,
+    Delete,
+    Merge,
+    Split,
+    Undo,
+    Redo,
+    Restore,
+    Approve,
+}
+
+// What a block looked like on one side of a change, enough to show it and to restore it
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub struct BlockSnapshot {
+    pub version: u64,
+    pub speaker: String,
+    pub text: String,
+    pub is_visible: bool,
+}
+
+// One change to one block, as the backend applied it. An operation touching several blocks (a
+// merge, a split) is an entry per block. `seq` counts the event's entries from 0.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub at_ms: u64, // Since the Unix epoch
+    pub user: String,
+    pub event_id: EventId,
+    pub block_id: BlockId,
+    pub action: AuditAction,
+    pub before: Option<BlockSnapshot>, // None for a block the change made (the new half of a split)
+    pub after: BlockSnapshot,
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// "2022-06-30 14:05:09 UTC". Days to dates after http://howardhinnant.github.io/date_algorithms.html
+pub fn format_utc(at_ms: u64) -> String {
+    let secs = at_ms / 1000;
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // March is 0
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
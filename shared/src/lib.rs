@@ -1,4 +1,19 @@
+use audit::AuditEntry;
 use moonlight::*;
+use ops::WordOp;
+use roles::Role;
+use speakers::{PersonId, SpeakerLibrary, SpeakerProfile, SpeakerProfiles};
+use std::collections::BTreeMap;
+
+#[cfg(feature = "accounts")]
+pub mod accounts;
+pub mod align;
+pub mod audit;
+pub mod ops;
+pub mod roles;
+pub mod speakers;
+pub mod split;
+pub mod transcription;
 
 pub type EventId = usize;
 pub type BlockId = usize;
@@ -8,10 +23,28 @@ pub type BlockId = usize;
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(crate = "serde")]
 pub enum UpMsg {
+    Login(LoginMessage),
+    Logout,
+    ListEvents,
     ChooseEvent(EventChoiceMessage),
-    EditBlock(BlockEdited),
+    Resync(EventChoiceMessage), // The client missed an update, it wants a new EventSnapshot
+    EditWords(WordsEdited),
     DeleteBlock(BlockMessage),
-    MergeBlockAbove(BlockMessage),
+    MergeBlockAbove(MergeBlockMessage),
+    SplitBlock(SplitBlockMessage),
+    Undo(EventChoiceMessage), // The user's latest delete, merge or split in the event
+    Redo(EventChoiceMessage),
+    ListAudit(AuditQuery),
+    RestoreBlock(RestoreBlockMessage),
+    ApproveBlock(BlockApproval),
+    RenameSpeaker(SpeakerRenamed),
+    ReassignBlockSpeaker(ReassignBlockSpeaker),
+    ListSpeakerLibrary(EventChoiceMessage),
+    AddPerson(NewPerson), // To the speaker library, linking the label to them
+    LinkSpeaker(SpeakerLink),
+    ListAccounts,
+    SetRole(RoleAssignment),
+    DeleteEvent(EventChoiceMessage), // With everything in it: audio, blocks, corrections, audit log
 }
 
 // ------ DownMsg ------
@@ -19,27 +52,344 @@ pub enum UpMsg {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(crate = "serde")]
 pub enum DownMsg {
+    LoggedIn(LoginAccepted),
+    LoginRejected(String),
+    AuthRequired, // The session's token is missing or expired, so its message was refused
+    PermissionDenied(String), // The user's role doesn't allow what they asked for
+    EventRole(EventRole), // What the user may do in the event they chose
+    EventList(Vec<EventSummary>),
     EventSelected(EventStreamMessage),
+    EventSnapshot(EventSnapshot), // Everything in the event so far, on choosing it or a resync
+    EventUpdate(EventUpdate), // Every change to an event's blocks comes numbered in one of these
+    BlockPartial(BlockMessage), // A live block still being transcribed, replaced by its BlockCreated
     BlockCreated(BlockMessage),
-    BlockEdited(BlockEdited),
+    WordsEdited(WordsEdited),
+    EditConflict(EditConflict), // Only to the session whose edit it was
     BlockDeleted(BlockMessage),
-    BlockMergedWithAbove(BlockMessage),
+    BlockMergedWithAbove(BlockMerged),
+    MergeRejected(MergeRejected), // Only to the session that asked for the merge
+    BlockSplit(BlockSplit),
+    BlockOrder(BlockOrder), // Blocks are no longer in id order, this is the order to show them in
+    BlocksRestored(BlocksRestored),
+    HistoryRejected(HistoryRejected), // Only to the session that asked for the undo/redo
+    AuditTrail(AuditTrail),           // Only to the session that asked
+    BlockApproved(BlockApproved),
+    SpeakerRenamed(SpeakerRenamed),
+    BlockSpeakerReassigned(BlockMessage), // The block as it stands with its new speaker
+    SpeakerRejected(SpeakerRejected),     // Only to the session that asked for the change
+    SpeakerLibrary(SpeakerLibrary),       // Only to the session that asked, or just linked
+    ApprovalRejected(ApprovalRejected),   // Only to the reviewer, the block changed or is gone
+    Accounts(Vec<AccountSummary>),        // Only to the admin that asked
+    EventFinished(EventChoiceMessage),
+    EventDeleted(EventChoiceMessage), // To whoever had it open, the last they'll hear of it
+}
+
+impl DownMsg {
+    // The event a message is about, None for the ones that aren't about any (logins, lists).
+    // Clients looking at another event ignore it.
+    pub fn event_id(&self) -> Option<EventId> {
+        let event_id = match self {
+            DownMsg::LoggedIn(_)
+            | DownMsg::LoginRejected(_)
+            | DownMsg::AuthRequired
+            | DownMsg::PermissionDenied(_)
+            | DownMsg::EventList(_)
+            | DownMsg::Accounts(_) => return None,
+            DownMsg::EventRole(msg) => msg.event_id,
+            DownMsg::EventSelected(msg) => msg.id,
+            DownMsg::EventSnapshot(msg) => msg.event_id,
+            DownMsg::EventUpdate(msg) => msg.event_id,
+            DownMsg::BlockPartial(msg)
+            | DownMsg::BlockCreated(msg)
+            | DownMsg::BlockDeleted(msg) => msg.event_id,
+            DownMsg::WordsEdited(msg) => msg.event_id,
+            DownMsg::EditConflict(msg) => msg.event_id,
+            DownMsg::BlockMergedWithAbove(msg) => msg.event_id,
+            DownMsg::MergeRejected(msg) => msg.event_id,
+            DownMsg::BlockSplit(msg) => msg.event_id,
+            DownMsg::BlockOrder(msg) => msg.event_id,
+            DownMsg::BlocksRestored(msg) => msg.event_id,
+            DownMsg::HistoryRejected(msg) => msg.event_id,
+            DownMsg::AuditTrail(msg) => msg.event_id,
+            DownMsg::BlockApproved(msg) => msg.event_id,
+            DownMsg::SpeakerRenamed(msg) => msg.event_id,
+            DownMsg::BlockSpeakerReassigned(msg) => msg.event_id,
+            DownMsg::SpeakerRejected(msg) => msg.event_id,
+            DownMsg::SpeakerLibrary(msg) => msg.event_id,
+            DownMsg::ApprovalRejected(msg) => msg.event_id,
+            DownMsg::EventFinished(msg) | DownMsg::EventDeleted(msg) => msg.id,
+        };
+        Some(event_id)
+    }
 }
 
 // ------ Message ------
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(crate = "serde")]
-pub struct BlockEdited {
+pub struct LoginMessage {
+    pub username: String,
+    pub password: String,
+}
+
+// The token goes with every UpMsg from then on, and survives a reload in local storage
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct LoginAccepted {
+    pub username: String,
+    pub token: String,
+    pub role: Role, // Outside any event, each event may give the user another
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct EventRole {
+    pub event_id: EventId,
+    pub role: Role,
+}
+
+// A username and their roles, for admins. Password hashes stay on the backend.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct AccountSummary {
+    pub username: String,
+    pub role: Role,
+    pub event_roles: BTreeMap<EventId, Role>,
+}
+
+// Give `username` a role across all events (no `event_id`) or in one. No `role` in an event drops
+// back to their role across all events.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct RoleAssignment {
+    pub username: String,
+    pub event_id: Option<EventId>,
+    pub role: Option<Role>,
+}
+
+// The event's visible blocks as they stand after update `seq`, top to bottom. The next update
+// is `seq + 1`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "serde")]
+pub struct EventSnapshot {
+    pub event_id: EventId,
+    pub seq: u64,
+    pub blocks: Vec<BlockMessage>,
+    pub approvals: Vec<BlockApproved>,
+    pub speakers: SpeakerProfiles,
+    pub audio_url: String,
+    pub is_finished: bool,
+}
+
+// A change to the event's blocks (a BlockCreated, a WordsEdited, ...), numbered from 1 per event.
+// A client that sees a number skipped has missed one and asks for a Resync.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "serde")]
+pub struct EventUpdate {
+    pub event_id: EventId,
+    pub seq: u64,
+    pub down_msg: Box<DownMsg>,
+}
+
+// A handful of word-level changes to one block, applied in order by the backend and every client.
+// `base_version` is the version of the block the ops were made against.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct WordsEdited {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub base_version: u64,
+    pub ops: Vec<WordOp>,
+}
+
+// An edit that clashed with somebody else's, sent back with the block as it stands now (None if
+// the block has since been deleted or merged away)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct EditConflict {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub rejected: Vec<WordOp>,
+    pub current: Option<BlockMessage>,
+}
+
+// Append block `id` to the visible block above it. Blocks with different speakers only merge with
+// `speaker` set, which the merged block (and all its words) then takes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct MergeBlockMessage {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub speaker: Option<String>,
+}
+
+// Block `id` is gone, its words now end `into`, the block above it as it stands after the merge
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct BlockMerged {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub into: BlockMessage,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct MergeRejected {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub reason: String,
+}
+
+// Cut block `id` before word `at_word_index`, the rest becoming a new block below it.
+// `version` is the version of the block the index was picked from.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct SplitBlockMessage {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub version: u64,
+    pub at_word_index: usize,
+    pub new_speaker: Option<String>, // None keeps the block's speaker for both halves
+}
+
+// Block `id` kept its words up to `at_word_index` (and moved up a version), the rest are
+// `new_block`. `order` is every visible block's id, top to bottom.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct BlockSplit {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub at_word_index: usize,
+    pub new_block: BlockMessage,
+    pub order: Vec<BlockId>,
+}
+
+// An undo or redo: `blocks` are back (or changed back) as they are now, `removed` are hidden again
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct BlocksRestored {
+    pub event_id: EventId,
+    pub blocks: Vec<BlockMessage>,
+    pub removed: Vec<BlockId>,
+    pub order: Vec<BlockId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct HistoryRejected {
+    pub event_id: EventId,
+    pub reason: String,
+}
+
+// The event's audit entries, or only one block's
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct AuditQuery {
+    pub event_id: EventId,
+    pub block_id: Option<BlockId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct AuditTrail {
+    pub event_id: EventId,
+    pub block_id: Option<BlockId>,
+    pub entries: Vec<AuditEntry>, // Oldest first
+}
+
+// Put block `id` back the way audit entry `seq` left it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct RestoreBlockMessage {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub seq: u64,
+}
+
+// A reviewer signs off on block `id` as it reads at `version`. Any later change to the block
+// needs approving again.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct BlockApproval {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub version: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct BlockApproved {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub version: u64,
+    pub approved_by: String,
+}
+
+// Label `label` is shown as `profile` throughout the event, blocks and exports alike
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct SpeakerRenamed {
+    pub event_id: EventId,
+    pub label: String,
+    pub profile: SpeakerProfile,
+}
+
+// Give block `id` (all its words) to the speaker labelled `speaker`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct ReassignBlockSpeaker {
+    pub event_id: EventId,
     pub id: BlockId,
     pub speaker: String,
-    pub corrected_text: String,
+}
+
+// Someone new for the speaker library, who label `label` of the event is
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct NewPerson {
+    pub event_id: EventId,
+    pub label: String,
+    pub name: String,
+    pub notes: Option<String>,
+}
+
+// Label `label` is `person_id` from the speaker library, and named after them. None unlinks it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct SpeakerLink {
+    pub event_id: EventId,
+    pub label: String,
+    pub person_id: Option<PersonId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct SpeakerRejected {
+    pub event_id: EventId,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct ApprovalRejected {
+    pub event_id: EventId,
+    pub id: BlockId,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct BlockOrder {
+    pub event_id: EventId,
+    pub order: Vec<BlockId>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(crate = "serde")]
 pub struct BlockMessage {
+    pub event_id: EventId,
     pub id: BlockId,
+    pub version: u64, // Goes up with every change to the block
     pub speaker: String,
     pub words: Vec<Word>,
 }
@@ -57,14 +407,30 @@ pub struct EventChoiceMessage {
     pub id: EventId,
 }
 
-// ////////////////////////////////////////////////////////////////////////////////////////////
-// Types for AAI data structures (used in deserialize calls)
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 #[serde(crate = "serde")]
-struct UploadResp {
-    upload_url: String, // url of file we uploaded (only accessible from AAI servers)
+pub enum EventStatus {
+    Pending,
+    Transcribing,
+    Live,
+    Finished,
+    Error,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "serde")]
+pub struct EventSummary {
+    pub id: EventId,
+    pub title: String,
+    pub date: String,
+    pub language: String,
+    pub audio_url: String,
+    pub status: EventStatus,
+    pub speakers: SpeakerProfiles,
+}
+
+// ////////////////////////////////////////////////////////////////////////////////////////////
+// Our transcript model, every `transcription::TranscriptionProvider` normalizes into these
 type Speaker = Option<String>; //  If it's provided, we get A, B, C, ... unclear what happens after Z, AWS allows 10
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -89,3 +455,21 @@ pub struct Utterance {
     pub text: String,
     pub words: Vec<Word>,
 }
+
+impl Utterance {
+    // Rebuild an utterance around a run of words (expected to be non-empty, and one speaker's)
+    pub fn from_words(words: Vec<Word>) -> Self {
+        Self {
+            confidence: words.iter().map(|w| w.confidence).sum::<f32>() / words.len().max(1) as f32,
+            audio_end: words.last().map_or(0, |w| w.end),
+            speaker: words.first().and_then(|w| w.speaker.clone()),
+            audio_start: words.first().map_or(0, |w| w.start),
+            text: words
+                .iter()
+                .map(|w| w.text.clone())
+                .collect::<Vec<String>>()
+                .join(" "),
+            words,
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use crate::align::{align_steps, align_words_within, spread_words, AlignStep};
+use crate::Word;
+use moonlight::{serde, Deserialize, Serialize};
+
+// ------ ------
+//     Types
+// ------ ------
+
+// One change to a block's words. Ranges are word indices, `start..end`, into the words as they
+// stand when the op is applied (so after the ops before it in the same edit).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub enum WordOp {
+    // The words in the range become `texts`, keeping the timings of the words that line up
+    ReplaceWords {
+        start: usize,
+        end: usize,
+        texts: Vec<String>,
+    },
+    // `texts` go in before word `at`, timed to fit the gap they land in
+    InsertWords {
+        at: usize,
+        texts: Vec<String>,
+    },
+    DeleteWords {
+        start: usize,
+        end: usize,
+    },
+    SetSpeaker {
+        start: usize,
+        end: usize,
+        speaker: Option<String>,
+    },
+}
+
+impl WordOp {
+    // Returns false, leaving the words alone, if the op doesn't fit them
+    pub fn apply(&self, words: &mut Vec<Word>) -> bool {
+        match self {
+            Self::ReplaceWords { start, end, texts } => {
+                if !is_range(*start, *end, words.len()) {
+                    return false;
+                }
+                let replacement = if start == end {
+                    fill_gap(words, *start, texts)
+                } else {
+                    // Words typed in at either end of the range may use the time up to its neighbours
+                    let window_start = match start.checked_sub(1) {
+                        Some(prev) => words[prev].end.min(words[*start].start),
+                        None => words[*start].start,
+                    };
+                    let window_end = words
+                        .get(*end)
+                        .map_or(words[*end - 1].end, |word| word.start)
+                        .max(words[*end - 1].end);
+                    align_words_within(
+                        &words[*start..*end],
+                        &texts.join(" "),
+                        window_start,
+                        window_end,
+                    )
+                };
+                words.splice(*start..*end, replacement);
+            }
+            Self::InsertWords { at, texts } => {
+                if *at > words.len() {
+                    return false;
+                }
+                let inserted = fill_gap(words, *at, texts);
+                words.splice(*at..*at, inserted);
+            }
+            Self::DeleteWords { start, end } => {
+                if !is_range(*start, *end, words.len()) {
+                    return false;
+                }
+                words.drain(*start..*end);
+            }
+            Self::SetSpeaker {
+                start,
+                end,
+                speaker,
+            } => {
+                if !is_range(*start, *end, words.len()) {
+                    return false;
+                }
+                for word in &mut words[*start..*end] {
+                    word.speaker = speaker.clone();
+                }
+            }
+        }
+        true
+    }
+
+    // The words this op reads or writes, as a `start..end` range (empty for an insert)
+    pub fn range(&self) -> (usize, usize) {
+        match self {
+            Self::ReplaceWords { start, end, .. }
+            | Self::DeleteWords { start, end }
+            | Self::SetSpeaker { start, end, .. } => (*start, *end),
+            Self::InsertWords { at, .. } => (*at, *at),
+        }
+    }
+
+    // How many words the op adds (or, negative, removes)
+    pub fn delta(&self) -> isize {
+        let (start, end) = self.range();
+        let removed = (end - start) as isize;
+        match self {
+            Self::ReplaceWords { texts, .. } => word_count(texts) as isize - removed,
+            Self::InsertWords { texts, .. } => word_count(texts) as isize,
+            Self::DeleteWords { .. } => -removed,
+            Self::SetSpeaker { .. } => 0,
+        }
+    }
+
+    // This op as it applies after `other` has been. Ops on separate words only need their indices
+    // shifted; ops on the same words (or inserts right up against them) are a real conflict, and
+    // get None: we can't know what either person meant the result to be.
+    pub fn transform(&self, other: &WordOp) -> Option<WordOp> {
+        let (start, end) = self.range();
+        let (other_start, other_end) = other.range();
+        let both_ranges = start < end && other_start < other_end;
+        if end < other_start || (end == other_start && both_ranges) {
+            Some(self.clone())
+        } else if start > other_end || (start == other_end && both_ranges) {
+            Some(self.shifted(other.delta()))
+        } else {
+            None
+        }
+    }
+
+    fn shifted(&self, by: isize) -> WordOp {
+        let shift = |idx: usize| (idx as isize + by) as usize;
+        let mut op = self.clone();
+        match &mut op {
+            Self::ReplaceWords { start, end, .. }
+            | Self::DeleteWords { start, end }
+            | Self::SetSpeaker { start, end, .. } => {
+                *start = shift(*start);
+                *end = shift(*end);
+            }
+            Self::InsertWords { at, .. } => *at = shift(*at),
+        }
+        op
+    }
+}
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// All of the ops or none of them, so a client never ends up with half an edit
+pub fn apply_ops(words: &mut Vec<Word>, ops: &[WordOp]) -> bool {
+    let mut edited = words.clone();
+    if ops.iter().all(|op| op.apply(&mut edited)) {
+        *words = edited;
+        true
+    } else {
+        false
+    }
+}
+
+// Rebases an edit made against an older version of a block onto the edits applied since, in the
+// order they were applied. None if any of them touch the same words.
+pub fn transform_ops(ops: &[WordOp], applied: &[WordOp]) -> Option<Vec<WordOp>> {
+    let mut applied = applied.to_vec();
+    let mut transformed = Vec::with_capacity(ops.len());
+    for op in ops {
+        // Each of our ops moves past everything applied, and everything applied moves past it,
+        // ready for our next op
+        let mut op = op.clone();
+        let mut applied_after = Vec::with_capacity(applied.len());
+        for other in &applied {
+            let op_after = op.transform(other)?;
+            applied_after.push(other.transform(&op)?);
+            op = op_after;
+        }
+        applied = applied_after;
+        transformed.push(op);
+    }
+    Some(transformed)
+}
+
+// The ops that turn `words` into `corrected_text`: one per run of changed words, last run first
+// so every op's indices still hold when it is applied. No change, no ops.
+pub fn diff_ops(words: &[Word], corrected_text: &str) -> Vec<WordOp> {
+    let tokens: Vec<&str> = corrected_text.split_whitespace().collect();
+    let mut ops = Vec::new();
+    let mut hunk: Option<(usize, usize, Vec<String>)> = None; // start, end, texts
+    let mut next_word = 0;
+    for step in align_steps(words, &tokens) {
+        let (consumed, token) = match step {
+            AlignStep::Keep(i, j) if words[i].text == tokens[j] => {
+                if let Some(hunk) = hunk.take() {
+                    ops.push(hunk_op(hunk));
+                }
+                next_word = i + 1;
+                continue;
+            }
+            AlignStep::Keep(_, j) | AlignStep::Substitute(_, j) => (1, Some(tokens[j])),
+            AlignStep::Insert(j) => (0, Some(tokens[j])),
+            AlignStep::Delete(_) => (1, None),
+        };
+        let (_, end, texts) = hunk.get_or_insert_with(|| (next_word, next_word, Vec::new()));
+        *end += consumed;
+        texts.extend(token.map(String::from));
+        next_word += consumed;
+    }
+    if let Some(hunk) = hunk {
+        ops.push(hunk_op(hunk));
+    }
+    ops.reverse();
+    ops
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+fn word_count(texts: &[String]) -> usize {
+    texts
+        .iter()
+        .map(|text| text.split_whitespace().count())
+        .sum()
+}
+
+fn is_range(start: usize, end: usize, len: usize) -> bool {
+    start <= end && end <= len
+}
+
+fn hunk_op((start, end, texts): (usize, usize, Vec<String>)) -> WordOp {
+    if start == end {
+        WordOp::InsertWords { at: start, texts }
+    } else if texts.is_empty() {
+        WordOp::DeleteWords { start, end }
+    } else {
+        WordOp::ReplaceWords { start, end, texts }
+    }
+}
+
+// New words between `words[at - 1]` and `words[at]`, in whatever time there is between them
+fn fill_gap(words: &[Word], at: usize, texts: &[String]) -> Vec<Word> {
+    let (prev, next) = (
+        at.checked_sub(1).and_then(|idx| words.get(idx)),
+        words.get(at),
+    );
+    let start = prev.map_or(next.map_or(0, |word| word.start), |word| word.end);
+    let end = next.map_or(start, |word| word.start);
+    let speaker = prev.or(next).and_then(|word| word.speaker.clone());
+    let texts: Vec<&str> = texts
+        .iter()
+        .flat_map(|text| text.split_whitespace())
+        .collect();
+    spread_words(&texts, start, end, speaker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A word a second, each 800ms long, all from speaker A
+    fn words(text: &str) -> Vec<Word> {
+        text.split_whitespace()
+            .enumerate()
+            .map(|(idx, text)| Word {
+                confidence: 1.0,
+                start: idx * 1000,
+                end: idx * 1000 + 800,
+                speaker: Some("A".to_string()),
+                text: text.to_string(),
+            })
+            .collect()
+    }
+
+    fn text(words: &[Word]) -> String {
+        words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    fn texts(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    fn applied(base: &str, ops: &[WordOp]) -> String {
+        let mut words = words(base);
+        assert!(
+            apply_ops(&mut words, ops),
+            "{:?} didn't apply to {:?}",
+            ops,
+            base
+        );
+        text(&words)
+    }
+
+    #[test]
+    fn diff_ops_apply_back_to_the_corrected_text() {
+        let cases = [
+            ("the quick brown fox", "the quick brown fox"),
+            ("the quick brown fox", "the quick red fox"),
+            ("the quick brown fox", "so the quick brown fox"),
+            ("the quick brown fox", "the quick brown fox jumps"),
+            ("the quick brown fox", "the very quick brown fox"),
+            ("the quick brown fox", "the fox"),
+            ("the quick brown fox", "a slow brown dog jumps"),
+            ("the quick brown fox", "one two"),
+            ("the quick brown fox", ""),
+            ("", "out of nowhere"),
+            ("um so uh we we start", "so we start now"),
+        ];
+        for (before, after) in cases {
+            let ops = diff_ops(&words(before), after);
+            assert_eq!(applied(before, &ops), after, "ops {:?}", ops);
+        }
+    }
+
+    #[test]
+    fn diff_ops_is_empty_without_a_change() {
+        let words = words("nothing to see here");
+        assert!(diff_ops(&words, "nothing  to see\nhere").is_empty());
+    }
+
+    #[test]
+    fn diff_ops_make_one_op_per_changed_run() {
+        let ops = diff_ops(&words("a b c d e f"), "a x c d f");
+        assert_eq!(
+            ops,
+            vec![
+                WordOp::DeleteWords { start: 4, end: 5 },
+                WordOp::ReplaceWords {
+                    start: 1,
+                    end: 2,
+                    texts: texts("x"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_ops_is_all_or_nothing() {
+        let mut words = words("a b c");
+        let ops = [
+            WordOp::DeleteWords { start: 0, end: 1 },
+            WordOp::DeleteWords { start: 2, end: 3 },
+        ];
+        assert!(!apply_ops(&mut words, &ops));
+        assert_eq!(text(&words), "a b c");
+    }
+
+    #[test]
+    fn inserted_words_fill_the_gap_they_land_in() {
+        let mut words = words("a b");
+        let op = WordOp::InsertWords {
+            at: 1,
+            texts: texts("x y"),
+        };
+        assert!(op.apply(&mut words));
+        assert_eq!(text(&words), "a x y b");
+        assert!(words[1].start >= 800 && words[2].end <= 1000);
+        assert_eq!(words[1].speaker.as_deref(), Some("A"));
+    }
+
+    // Two people edit the same version; applying either one's op and then the other's transformed
+    // op has to end up in the same place
+    fn assert_converges(base: &str, a: WordOp, b: WordOp) {
+        let b_after_a = b.transform(&a).expect("b should rebase onto a");
+        let a_after_b = a.transform(&b).expect("a should rebase onto b");
+        let a_first = applied(base, &[a.clone(), b_after_a]);
+        let b_first = applied(base, &[b.clone(), a_after_b]);
+        assert_eq!(a_first, b_first, "{:?} and {:?}", a, b);
+    }
+
+    #[test]
+    fn concurrent_ops_on_separate_words_converge() {
+        let base = "one two three four five six";
+        let insert = |at, text| WordOp::InsertWords {
+            at,
+            texts: texts(text),
+        };
+        let delete = |start, end| WordOp::DeleteWords { start, end };
+        let replace = |start, end, text| WordOp::ReplaceWords {
+            start,
+            end,
+            texts: texts(text),
+        };
+        assert_converges(base, insert(1, "and"), delete(3, 5));
+        assert_converges(base, delete(0, 2), insert(4, "and a half"));
+        assert_converges(base, replace(1, 3, "2 3 3.5"), delete(4, 6));
+        assert_converges(base, delete(1, 2), replace(4, 5, "FIVE"));
+        assert_converges(base, insert(6, "seven"), replace(0, 1, "zero one"));
+        assert_converges(base, replace(0, 2, "1"), replace(2, 3, "3"));
+        assert_converges(
+            base,
+            WordOp::SetSpeaker {
+                start: 3,
+                end: 6,
+                speaker: Some("B".to_string()),
+            },
+            insert(1, "uh"),
+        );
+        assert_eq!(
+            applied(
+                base,
+                &[
+                    insert(1, "and"),
+                    delete(3, 5).transform(&insert(1, "and")).unwrap()
+                ]
+            ),
+            "one and two three six"
+        );
+    }
+
+    #[test]
+    fn concurrent_ops_on_the_same_words_conflict() {
+        let replace = WordOp::ReplaceWords {
+            start: 1,
+            end: 3,
+            texts: texts("x"),
+        };
+        let delete = WordOp::DeleteWords { start: 2, end: 4 };
+        let insert_inside = WordOp::InsertWords {
+            at: 2,
+            texts: texts("y"),
+        };
+        let insert_against = WordOp::InsertWords {
+            at: 3,
+            texts: texts("z"),
+        };
+        assert_eq!(replace.transform(&delete), None);
+        assert_eq!(delete.transform(&replace), None);
+        assert_eq!(insert_inside.transform(&replace), None);
+        assert_eq!(insert_against.transform(&replace), None);
+        assert_eq!(replace.transform(&insert_against), None);
+        assert_eq!(insert_against.transform(&insert_against), None);
+    }
+
+    #[test]
+    fn transform_ops_rebases_past_every_applied_op() {
+        let base = "a b c d e f g h";
+        let applied_ops = [
+            WordOp::InsertWords {
+                at: 0,
+                texts: texts("so um"),
+            },
+            WordOp::DeleteWords { start: 2, end: 3 },
+        ];
+        let ops = [
+            WordOp::ReplaceWords {
+                start: 5,
+                end: 6,
+                texts: texts("F"),
+            },
+            WordOp::DeleteWords { start: 7, end: 8 },
+        ];
+        let rebased = transform_ops(&ops, &applied_ops).unwrap();
+        let mut all = applied_ops.to_vec();
+        all.extend(rebased);
+        assert_eq!(applied(base, &all), "so um b c d e F g");
+
+        let clashing = [WordOp::DeleteWords { start: 0, end: 1 }];
+        assert_eq!(transform_ops(&clashing, &applied_ops), None);
+    }
+}
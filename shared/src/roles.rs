@@ -0,0 +1,33 @@
+use moonlight::{serde, Deserialize, Serialize};
+
+// ------ ------
+//     Types
+// ------ ------
+
+// What a user may do, each role able to do everything the ones before it can:
+// viewers only look, editors change blocks, reviewers approve them, admins manage events and users
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(crate = "serde")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Reviewer,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Viewer, Role::Editor, Role::Reviewer, Role::Admin];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Reviewer => "reviewer",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
+}
@@ -0,0 +1,120 @@
+use crate::EventId;
+use moonlight::{serde, Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// ------ ------
+//     Types
+// ------ ------
+
+pub type PersonId = u64;
+
+// How an event shows one diarization label (A, B, ...), e.g. "A" is Mayor Jones, the Chair, in blue
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default, Debug)]
+#[serde(crate = "serde", from = "StoredProfile")]
+pub struct SpeakerProfile {
+    pub name: String,
+    pub color: Option<String>, // Any CSS color
+    pub role: Option<String>,
+    pub person: Option<PersonId>, // Who in the speaker library this is, if anyone
+}
+
+// Keyed by label
+pub type SpeakerProfiles = BTreeMap<String, SpeakerProfile>;
+
+// Manifests from before profiles only named their speakers
+#[derive(Deserialize)]
+#[serde(crate = "serde", untagged)]
+enum StoredProfile {
+    Name(String),
+    Profile {
+        name: String,
+        #[serde(default)]
+        color: Option<String>,
+        #[serde(default)]
+        role: Option<String>,
+        #[serde(default)]
+        person: Option<PersonId>,
+    },
+}
+
+impl From<StoredProfile> for SpeakerProfile {
+    fn from(stored: StoredProfile) -> Self {
+        match stored {
+            StoredProfile::Name(name) => Self {
+                name,
+                ..Self::default()
+            },
+            StoredProfile::Profile {
+                name,
+                color,
+                role,
+                person,
+            } => Self {
+                name,
+                color,
+                role,
+                person,
+            },
+        }
+    }
+}
+
+// Someone who speaks at event after event, like a council member. Kept in the backend's speaker
+// library, which every event's labels can link to.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub struct Person {
+    pub id: PersonId,
+    pub name: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+// A person and how many events link a label to them
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub struct LibraryPerson {
+    pub person: Person,
+    pub events: usize,
+}
+
+// `person_id` was linked to a speaker of this name in `times` other events; 0 if only their name
+// in the library matches
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub struct Suggestion {
+    pub person_id: PersonId,
+    pub times: usize,
+}
+
+// The library as seen from one event: everyone in it, most used first, and for each speaker name
+// (by `name_key`) the people it was linked to in other events, most often first. Labels are only
+// the diarizer's letters, "A" in one event is nobody in particular in the next.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "serde")]
+pub struct SpeakerLibrary {
+    pub event_id: EventId,
+    pub people: Vec<LibraryPerson>,
+    pub suggestions: BTreeMap<String, Vec<Suggestion>>,
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+// Speakers nobody has named keep their diarization label
+pub fn display_name(profiles: &SpeakerProfiles, label: &str) -> String {
+    match profiles.get(label) {
+        Some(profile) if !profile.name.is_empty() => profile.name.clone(),
+        _ if label.is_empty() => "Unknown speaker".to_string(),
+        _ => format!("Speaker {}", label),
+    }
+}
+
+// "Mayor  Jones" and "mayor jones" are the same speaker
+pub fn name_key(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
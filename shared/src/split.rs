@@ -0,0 +1,212 @@
+use crate::{BlockId, Utterance, Word};
+use moonlight::serde_json;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+// ------ ------
+//     Types
+// ------ ------
+
+#[derive(Clone, Debug)]
+pub struct SplitOptions {
+    pub max_duration_ms: usize,
+    pub max_words: usize,
+}
+
+impl Default for SplitOptions {
+    // About what fits comfortably in the editor's table, and in a reviewer's head
+    fn default() -> Self {
+        Self {
+            max_duration_ms: 30_000,
+            max_words: 60,
+        }
+    }
+}
+
+// ------ ------
+//   Commands
+// ------ ------
+
+// Turns a whole transcript into blocks: a new block whenever the speaker changes, or when the
+// current one would run past `max_duration_ms` or `max_words`. A block cut short for length
+// ends on the last sentence in its second half, if it has one, rather than mid-sentence.
+pub fn split_transcript(utterances: Vec<Utterance>, options: &SplitOptions) -> Vec<Utterance> {
+    let words = utterances.into_iter().flat_map(|utterance| {
+        let speaker = utterance.speaker;
+        utterance.words.into_iter().map(move |mut word| {
+            // Utterance-level labels win over nothing at all
+            if word.speaker.is_none() {
+                word.speaker = speaker.clone();
+            }
+            word
+        })
+    });
+
+    let mut blocks: Vec<Vec<Word>> = Vec::new();
+    let mut current: Vec<Word> = Vec::new();
+    for word in words {
+        if let Some(first) = current.first() {
+            if first.speaker != word.speaker {
+                blocks.push(std::mem::take(&mut current));
+            } else if current.len() >= options.max_words
+                || word.end.saturating_sub(first.start) > options.max_duration_ms
+            {
+                let carried = match sentence_end(&current) {
+                    Some(idx) => current.split_off(idx + 1),
+                    None => Vec::new(),
+                };
+                blocks.push(std::mem::replace(&mut current, carried));
+            }
+        }
+        current.push(word);
+    }
+    if !current.is_empty() {
+        blocks.push(current);
+    }
+
+    blocks.into_iter().map(Utterance::from_words).collect()
+}
+
+// Writes `block_NNNN.json` files numbered from `first_id`, returning how many were written. Each
+// file is written to the side and renamed into place, so a watcher never sees half a block.
+pub fn write_block_files(dir: &Path, blocks: &[Utterance], first_id: BlockId) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+    for (idx, block) in blocks.iter().enumerate() {
+        let path = dir.join(format!("block_{:04}.json", first_id + idx));
+        let tmp_path = path.with_extension("json.tmp");
+        serde_json::to_writer(File::create(&tmp_path)?, block)?;
+        fs::rename(tmp_path, path)?;
+    }
+    Ok(blocks.len())
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+fn sentence_end(words: &[Word]) -> Option<usize> {
+    let idx = words
+        .iter()
+        .rposition(|word| word.text.ends_with(['.', '?', '!']))?;
+    (idx + 1 >= words.len() / 2).then_some(idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One utterance with a word a second, each 800ms long
+    fn utterance(speaker: Option<&str>, first: usize, text: &str) -> Utterance {
+        Utterance::from_words(
+            text.split_whitespace()
+                .enumerate()
+                .map(|(idx, text)| Word {
+                    confidence: 1.0,
+                    start: (first + idx) * 1000,
+                    end: (first + idx) * 1000 + 800,
+                    speaker: speaker.map(String::from),
+                    text: text.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    fn texts(blocks: &[Utterance]) -> Vec<&str> {
+        blocks.iter().map(|block| block.text.as_str()).collect()
+    }
+
+    fn options(max_duration_ms: usize, max_words: usize) -> SplitOptions {
+        SplitOptions {
+            max_duration_ms,
+            max_words,
+        }
+    }
+
+    #[test]
+    fn a_new_block_for_each_speaker() {
+        let blocks = split_transcript(
+            vec![
+                utterance(Some("A"), 0, "hello there."),
+                utterance(Some("B"), 2, "hi."),
+                utterance(Some("B"), 3, "how are you?"),
+                utterance(Some("A"), 6, "fine."),
+            ],
+            &SplitOptions::default(),
+        );
+        assert_eq!(
+            texts(&blocks),
+            vec!["hello there.", "hi. how are you?", "fine."]
+        );
+        assert_eq!(blocks[1].speaker.as_deref(), Some("B"));
+        assert_eq!((blocks[1].audio_start, blocks[1].audio_end), (2000, 5800));
+    }
+
+    #[test]
+    fn words_without_a_speaker_take_the_utterance_label() {
+        let mut unlabelled = utterance(None, 0, "one two");
+        unlabelled.speaker = Some("C".to_string());
+        let blocks = split_transcript(vec![unlabelled], &SplitOptions::default());
+        assert!(blocks[0]
+            .words
+            .iter()
+            .all(|word| word.speaker.as_deref() == Some("C")));
+    }
+
+    #[test]
+    fn long_blocks_are_cut_at_max_words() {
+        let blocks = split_transcript(
+            vec![utterance(Some("A"), 0, "a b c d e f g")],
+            &options(60_000, 3),
+        );
+        assert_eq!(texts(&blocks), vec!["a b c", "d e f", "g"]);
+    }
+
+    #[test]
+    fn a_cut_ends_on_a_sentence_in_the_second_half() {
+        let blocks = split_transcript(
+            vec![utterance(
+                Some("A"),
+                0,
+                "one two three. four five six seven",
+            )],
+            &options(60_000, 5),
+        );
+        assert_eq!(
+            texts(&blocks),
+            vec!["one two three.", "four five six seven"]
+        );
+    }
+
+    #[test]
+    fn a_sentence_in_the_first_half_is_not_worth_cutting_at() {
+        let blocks = split_transcript(
+            vec![utterance(Some("A"), 0, "yes. one two three four five six")],
+            &options(60_000, 5),
+        );
+        assert_eq!(texts(&blocks), vec!["yes. one two three four", "five six"]);
+    }
+
+    #[test]
+    fn long_blocks_are_cut_at_max_duration() {
+        // The fourth word would end at 3.8s, past the 3.5s limit
+        let blocks = split_transcript(
+            vec![utterance(Some("A"), 0, "a b c d e f")],
+            &options(3_500, 60),
+        );
+        assert_eq!(texts(&blocks), vec!["a b c", "d e f"]);
+        assert_eq!((blocks[1].audio_start, blocks[1].audio_end), (3000, 5800));
+    }
+
+    #[test]
+    fn block_files_are_numbered_from_the_first_id() {
+        let dir = std::env::temp_dir().join(format!("jadili_split_{}", std::process::id()));
+        let blocks = vec![utterance(Some("A"), 0, "a"), utterance(Some("B"), 1, "b")];
+        assert_eq!(write_block_files(&dir, &blocks, 7).unwrap(), 2);
+        let written: Utterance =
+            serde_json::from_reader(File::open(dir.join("block_0008.json")).unwrap()).unwrap();
+        assert_eq!(written.text, "b");
+        assert!(!dir.join("block_0008.json.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
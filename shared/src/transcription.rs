@@ -0,0 +1,378 @@
+use crate::{Utterance, Word};
+use moonlight::{serde, serde_json, Deserialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// ------ ------
+//    Errors
+// ------ ------
+
+#[derive(Debug)]
+pub enum ProviderError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Format(String), // Valid JSON, but not a shape we can make a transcript from
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read transcript: {}", err),
+            Self::Json(err) => write!(f, "cannot parse transcript: {}", err),
+            Self::Format(msg) => write!(f, "unexpected transcript: {}", msg),
+        }
+    }
+}
+
+impl Error for ProviderError {}
+
+impl From<io::Error> for ProviderError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ProviderError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+// ------ ------
+//    Provider
+// ------ ------
+
+// A speech-to-text service whose finished transcript we can turn into jadili's own `Utterance`s
+pub trait TranscriptionProvider {
+    fn name(&self) -> &'static str;
+
+    fn normalize(&self, raw: &str) -> Result<Vec<Utterance>, ProviderError>;
+}
+
+// For configuration: "assembly_ai", "aws_transcribe" or "whisper"
+pub fn provider_by_name(name: &str) -> Option<Box<dyn TranscriptionProvider + Send + Sync>> {
+    match name {
+        "assembly_ai" => Some(Box::new(AssemblyAi)),
+        "aws_transcribe" => Some(Box::new(AwsTranscribe)),
+        "whisper" => Some(Box::new(Whisper)),
+        _ => None,
+    }
+}
+
+// ------ AssemblyAI ------
+
+// https://docs.assemblyai.com/core-transcription
+pub struct AssemblyAi;
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AaiTranscript {
+    utterances: Option<Vec<AaiUtterance>>,
+    words: Option<Vec<Word>>,
+}
+
+// The upload API calls it start/end, where realtime (and our block files) say audio_start/audio_end
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AaiUtterance {
+    confidence: f32,
+    end: usize,
+    speaker: Option<String>,
+    start: usize,
+    text: String,
+    words: Vec<Word>,
+}
+
+impl TranscriptionProvider for AssemblyAi {
+    fn name(&self) -> &'static str {
+        "assembly_ai"
+    }
+
+    fn normalize(&self, raw: &str) -> Result<Vec<Utterance>, ProviderError> {
+        let transcript: AaiTranscript = serde_json::from_str(raw)?;
+        match (transcript.utterances, transcript.words) {
+            (Some(utterances), _) => Ok(utterances
+                .into_iter()
+                .map(|utterance| Utterance {
+                    confidence: utterance.confidence,
+                    audio_end: utterance.end,
+                    speaker: utterance.speaker,
+                    audio_start: utterance.start,
+                    text: utterance.text,
+                    words: utterance.words,
+                })
+                .collect()),
+            // No speaker_labels asked for, we only get the word stream
+            (None, Some(words)) => Ok(group_by_speaker(words)),
+            (None, None) => Err(ProviderError::Format("no utterances or words".into())),
+        }
+    }
+}
+
+// ------ AWS Transcribe ------
+
+// Times are strings of seconds, speakers are spk_0, spk_1, ... and punctuation comes as its own item
+pub struct AwsTranscribe;
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AwsTranscript {
+    results: AwsResults,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AwsResults {
+    items: Vec<AwsItem>,
+    speaker_labels: Option<AwsSpeakerLabels>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AwsItem {
+    start_time: Option<String>,
+    end_time: Option<String>,
+    alternatives: Vec<AwsAlternative>,
+    #[serde(rename = "type")]
+    kind: String, // pronunciation, punctuation
+    speaker_label: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AwsAlternative {
+    confidence: String,
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AwsSpeakerLabels {
+    segments: Vec<AwsSegment>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AwsSegment {
+    items: Vec<AwsSegmentItem>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct AwsSegmentItem {
+    start_time: String,
+    speaker_label: String,
+}
+
+impl TranscriptionProvider for AwsTranscribe {
+    fn name(&self) -> &'static str {
+        "aws_transcribe"
+    }
+
+    fn normalize(&self, raw: &str) -> Result<Vec<Utterance>, ProviderError> {
+        let transcript: AwsTranscript = serde_json::from_str(raw)?;
+        let results = transcript.results;
+
+        // Older output only names speakers in the segments, keyed by the item's start time
+        let segment_speakers: Vec<(String, String)> = results
+            .speaker_labels
+            .map(|labels| {
+                labels
+                    .segments
+                    .into_iter()
+                    .flat_map(|segment| segment.items)
+                    .map(|item| (item.start_time, item.speaker_label))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut words: Vec<Word> = Vec::new();
+        for item in results.items {
+            let alternative = match item.alternatives.into_iter().next() {
+                Some(alternative) => alternative,
+                None => continue,
+            };
+            if item.kind == "punctuation" {
+                if let Some(word) = words.last_mut() {
+                    word.text.push_str(&alternative.content);
+                }
+                continue;
+            }
+
+            let start_time = item.start_time.unwrap_or_default();
+            let speaker = item.speaker_label.or_else(|| {
+                segment_speakers
+                    .iter()
+                    .find(|(time, _)| *time == start_time)
+                    .map(|(_, label)| label.clone())
+            });
+            words.push(Word {
+                confidence: alternative.confidence.parse().unwrap_or(0.0),
+                end: seconds_to_ms(&item.end_time.unwrap_or_default())?,
+                speaker: speaker.as_deref().map(aws_speaker),
+                start: seconds_to_ms(&start_time)?,
+                text: alternative.content,
+            });
+        }
+        Ok(group_by_speaker(words))
+    }
+}
+
+// spk_0 -> A, spk_1 -> B, ... so every provider labels speakers the same way
+fn aws_speaker(label: &str) -> String {
+    match label
+        .strip_prefix("spk_")
+        .and_then(|idx| idx.parse::<u8>().ok())
+    {
+        Some(idx) if idx < 26 => char::from(b'A' + idx).to_string(),
+        _ => label.to_string(),
+    }
+}
+
+// ------ Whisper ------
+
+// openai-whisper's JSON output; no diarization, and words only with `--word_timestamps True`
+pub struct Whisper;
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct WhisperTranscript {
+    segments: Vec<WhisperSegment>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct WhisperSegment {
+    start: f64,
+    end: f64,
+    text: String,
+    avg_logprob: Option<f64>,
+    #[serde(default)]
+    words: Vec<WhisperWord>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "serde")]
+struct WhisperWord {
+    word: String,
+    start: f64,
+    end: f64,
+    probability: Option<f32>,
+}
+
+impl TranscriptionProvider for Whisper {
+    fn name(&self) -> &'static str {
+        "whisper"
+    }
+
+    fn normalize(&self, raw: &str) -> Result<Vec<Utterance>, ProviderError> {
+        let transcript: WhisperTranscript = serde_json::from_str(raw)?;
+        Ok(transcript
+            .segments
+            .into_iter()
+            .filter(|segment| !segment.text.trim().is_empty())
+            .map(|segment| {
+                let segment_confidence = segment.avg_logprob.map_or(1.0, |p| p.exp() as f32);
+                let words = if segment.words.is_empty() {
+                    // No word timings, spread the segment evenly over its words
+                    let texts: Vec<&str> = segment.text.split_whitespace().collect();
+                    let start = (segment.start * 1000.0).round() as usize;
+                    let step = ((segment.end - segment.start) * 1000.0).max(0.0) as usize
+                        / texts.len().max(1);
+                    texts
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, text)| Word {
+                            confidence: segment_confidence,
+                            end: start + step * (idx + 1),
+                            speaker: None,
+                            start: start + step * idx,
+                            text: text.to_string(),
+                        })
+                        .collect()
+                } else {
+                    segment
+                        .words
+                        .into_iter()
+                        .map(|word| Word {
+                            confidence: word.probability.unwrap_or(segment_confidence),
+                            end: (word.end * 1000.0).round() as usize,
+                            speaker: None,
+                            start: (word.start * 1000.0).round() as usize,
+                            text: word.word.trim().to_string(),
+                        })
+                        .collect()
+                };
+                Utterance {
+                    confidence: segment_confidence,
+                    audio_end: (segment.end * 1000.0).round() as usize,
+                    speaker: None,
+                    audio_start: (segment.start * 1000.0).round() as usize,
+                    text: segment.text.trim().to_string(),
+                    words,
+                }
+            })
+            .collect())
+    }
+}
+
+// ------ Offline ------
+
+// Reads a transcript somebody already made, in any provider's format; no network, no waiting
+pub struct OfflineProvider {
+    path: PathBuf,
+    format: Box<dyn TranscriptionProvider + Send + Sync>,
+}
+
+impl OfflineProvider {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        format: Box<dyn TranscriptionProvider + Send + Sync>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            format,
+        }
+    }
+
+    pub fn transcribe(&self) -> Result<Vec<Utterance>, ProviderError> {
+        let raw = fs::read_to_string(&self.path)?;
+        self.format.normalize(&raw)
+    }
+}
+
+impl TranscriptionProvider for OfflineProvider {
+    fn name(&self) -> &'static str {
+        "offline"
+    }
+
+    fn normalize(&self, raw: &str) -> Result<Vec<Utterance>, ProviderError> {
+        self.format.normalize(raw)
+    }
+}
+
+// ------ ------
+//    Helpers
+// ------ ------
+
+fn seconds_to_ms(seconds: &str) -> Result<usize, ProviderError> {
+    let seconds: f64 = seconds
+        .parse()
+        .map_err(|_| ProviderError::Format(format!("bad time {:?}", seconds)))?;
+    Ok((seconds * 1000.0).round() as usize)
+}
+
+// Runs of words from the same speaker make an utterance
+fn group_by_speaker(words: Vec<Word>) -> Vec<Utterance> {
+    let mut runs: Vec<Vec<Word>> = Vec::new();
+    for word in words {
+        match runs.last_mut() {
+            Some(run) if run[0].speaker == word.speaker => run.push(word),
+            _ => runs.push(vec![word]),
+        }
+    }
+    runs.into_iter().map(Utterance::from_words).collect()
+}